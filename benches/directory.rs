@@ -0,0 +1,80 @@
+//! Lookup latency of the daemon directory against a linear scan of the sectors.
+//!
+//! Run with `cargo bench --bench directory`.
+
+#[allow(dead_code)]
+#[path = "../src/directory.rs"]
+mod directory;
+#[allow(dead_code)]
+#[path = "../src/error.rs"]
+mod error;
+#[allow(dead_code)]
+#[path = "../src/structs.rs"]
+mod structs;
+
+use directory::Directory;
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::time::Instant;
+use structs::{Member, Sector, SectorGroup, SectorType};
+
+const LOOKUPS: u64 = 10_000;
+const MEMBERS_PER_SECTOR: u64 = 500;
+
+fn member(id: u64) -> (String, Member) {
+    (format!("user{}", id),
+     Member { id: 10_000 + id,
+              login: format!("user{}", id) })
+}
+
+fn sector(s: u64, members: HashMap<String, Member>) -> SectorGroup {
+    SectorGroup { sector: Sector { id: 1_000 + s,
+                                   name: format!("sector{}", s),
+                                   sector_type: SectorType::Team },
+                  gid: None,
+                  group: None,
+                  members }
+}
+
+fn sectors(members: u64) -> Vec<SectorGroup> {
+    (0..members.div_ceil(MEMBERS_PER_SECTOR)).map(|s| {
+                                                 let ids = s * MEMBERS_PER_SECTOR
+                                                           ..((s + 1) * MEMBERS_PER_SECTOR).min(members);
+                                                 sector(s, ids.map(member).collect())
+                                             })
+                                             .collect()
+}
+
+fn linear_uid(sectors: &[SectorGroup], uid: u64) -> Option<&Member> {
+    sectors.iter().find_map(|s| s.members.values().find(|m| m.id == uid))
+}
+
+/// Nanoseconds per call of `f` over `LOOKUPS` uids spread across all members
+fn measure(members: u64, mut f: impl FnMut(u64)) -> u128 {
+    let start = Instant::now();
+    for i in 0..LOOKUPS {
+        f(10_000 + (i * 7919) % members);
+    }
+    start.elapsed().as_nanos() / u128::from(LOOKUPS)
+}
+
+fn main() {
+    println!("{:>8} {:>12} {:>14} {:>14} {:>14}",
+             "members", "build(ms)", "uid(ns)", "login(ns)", "linear(ns)");
+    for members in [1_000, 10_000, 50_000, 100_000] {
+        let sectors = sectors(members);
+        let start = Instant::now();
+        let directory = Directory::new(sectors.clone(), true);
+        let build = start.elapsed().as_millis();
+        let uid = measure(members, |uid| {
+            black_box(directory.user_by_uid(black_box(uid)));
+        });
+        let login = measure(members, |uid| {
+            black_box(directory.user_by_login(black_box(&format!("user{}", uid - 10_000))));
+        });
+        let linear = measure(members, |uid| {
+            black_box(linear_uid(&sectors, black_box(uid)));
+        });
+        println!("{:>8} {:>12} {:>14} {:>14} {:>14}", members, build, uid, login, linear);
+    }
+}
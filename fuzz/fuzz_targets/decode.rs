@@ -0,0 +1,31 @@
+//! Decoding of arbitrary frames must fail with an error and never panic.
+//!
+//! Run with `cargo +nightly fuzz run decode` from the repository root.
+
+#![no_main]
+
+#[allow(dead_code)]
+#[path = "../../src/error.rs"]
+mod error;
+#[allow(dead_code)]
+#[path = "../../src/message.rs"]
+mod message;
+#[allow(dead_code)]
+#[path = "../../src/structs.rs"]
+mod structs;
+
+use libfuzzer_sys::fuzz_target;
+use message::{ClientMessage, DaemonMessage};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(msg) = message::decode::<ClientMessage>(data) {
+        // Whatever decodes must encode back to a frame the peer accepts
+        let frame = message::encode(&msg).expect("encode decoded client message");
+        message::decode::<ClientMessage>(&frame).expect("decode re-encoded client message");
+    }
+    if let Ok(msg) = message::decode::<DaemonMessage>(data) {
+        let frame = message::encode(&msg).expect("encode decoded daemon message");
+        message::decode::<DaemonMessage>(&frame).expect("decode re-encoded daemon message");
+    }
+    let _ = message::read_frame(&mut &data[..], message::MAX_FRAME_LEN);
+});
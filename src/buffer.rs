@@ -25,31 +25,30 @@ impl Buffer {
         }
     }
 
-    #[allow(clippy::cast_ptr_alignment)] // NOTE: waiting for align_offset https://github.com/rust-lang/rust/issues/44488
+    #[allow(clippy::cast_ptr_alignment)] // aligned by `align_offset` below
     fn add_pointers(&mut self, ptrs: &[*mut libc::c_char]) -> Result<*mut *mut libc::c_char, Error> {
-        use std::mem::size_of;
-        let step = std::cmp::max(size_of::<*mut libc::c_char>() / size_of::<libc::c_char>(), 1);
-        let align_offset = self.offset % step as isize;
-        self.offset += align_offset; // NOTE: remove after stabilization of align_offset
-        if self.buflen < (((ptrs.len() + 1) * step) as isize + self.offset) as libc::size_t {
+        use std::mem::{align_of, size_of};
+        let step = size_of::<*mut libc::c_char>();
+        let pad = unsafe { self.buf.offset(self.offset) }.align_offset(align_of::<*mut libc::c_char>());
+        if pad == usize::MAX || self.buflen < pad + (ptrs.len() + 1) * step {
             return Err(Error::new(ErrorKind::AddrNotAvailable, "ERANGE"));
         }
+        self.offset += pad as isize;
+        self.buflen -= pad;
         unsafe {
             let mem = self.buf.offset(self.offset) as *mut *mut libc::c_char;
             for (i, p) in ptrs.iter().enumerate() {
                 *(mem.add(i)) = *p;
-                self.offset += step as isize;
-                self.buflen -= step as libc::size_t;
             }
             *(mem.add(ptrs.len())) = std::ptr::null_mut::<libc::c_char>();
-            self.offset += step as isize;
-            self.buflen -= step as libc::size_t;
+            self.offset += ((ptrs.len() + 1) * step) as isize;
+            self.buflen -= (ptrs.len() + 1) * step;
             Ok(mem)
         }
     }
 
     pub fn write_string(&mut self, s: &str) -> Result<*mut libc::c_char, Error> {
-        let cs = CString::new(s).unwrap();
+        let cs = CString::new(s).map_err(|_| Error::new(ErrorKind::InvalidData, "string contains a nul byte"))?;
         self.write(cs.as_ptr(), s.len() + 1)
     }
 
@@ -1,55 +1,58 @@
 use crate::applog;
-use crate::error;
+use crate::error::{self, ParseMessageError};
 use crate::message::*;
 use crate::structs::SocketConfig as Config;
-use std::os::unix::net::UnixDatagram;
+use std::io::{self, Write};
+use std::os::unix::net::UnixStream;
 use std::time::Duration;
 
 #[derive(Debug)]
 pub struct Connection {
-    conf: Config,
-    conn: UnixDatagram,
+    conn: UnixStream,
 }
 
 impl Connection {
     pub fn new(logid: &str) -> Result<Self, error::Error> {
         applog::init(Some("sectora"));
         log::debug!("{}", logid);
-        let conf = Config::new();
-        let conn = Self::connect_daemon(&conf)?;
-        Ok(Self { conf, conn })
+        let conn = Self { conn: Self::connect_daemon(&Config::new())? };
+        conn.handshake()?;
+        Ok(conn)
     }
 
-    fn socket_path(conf: &Config) -> String { format!("{}/{}", &conf.socket_dir, std::process::id()) }
-
-    fn connect_daemon(conf: &Config) -> Result<UnixDatagram, error::Error> {
-        let socket = UnixDatagram::bind(Self::socket_path(conf))?;
-        log::debug!("{:?}", socket);
+    /// The daemon identifies the caller with SO_PEERCRED, so the client socket needs no address of its own
+    fn connect_daemon(conf: &Config) -> Result<UnixStream, error::Error> {
+        let socket = match UnixStream::connect(&conf.socket_path) {
+            Ok(socket) => socket,
+            Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused) => {
+                return Err(error::Error::NotRunning);
+            }
+            Err(e) => return Err(e.into()),
+        };
         socket.set_read_timeout(Some(Duration::from_secs(5)))?;
-        socket.connect(&conf.socket_path)?;
+        socket.set_write_timeout(Some(Duration::from_secs(5)))?;
+        log::debug!("{:?}", socket);
         Ok(socket)
     }
 
-    pub fn communicate(&self, msg: ClientMessage) -> Result<DaemonMessage, error::Error> {
-        self.conn.send(msg.to_string().as_bytes())?;
-        let mut msgstr = String::default();
-        let mut buf = [0u8; 4096];
-        while let Ok(cnt) = self.conn.recv(&mut buf) {
-            log::debug!("msg cnt, {}", cnt);
-            let msg = String::from_utf8(buf[..cnt].to_vec()).unwrap()
-                                                            .parse::<DividedMessage>()?;
-            msgstr.push_str(&msg.message);
-            if msg.cont {
-                let _ = self.conn.send(ClientMessage::Cont.to_string().as_bytes())?;
-            } else {
-                break;
+    /// Fails before any request is sent when the daemon speaks another protocol version
+    fn handshake(&self) -> Result<(), error::Error> {
+        match self.communicate(ClientMessage::Hello { version: PROTOCOL_VERSION })? {
+            DaemonMessage::Hello { version } if version == PROTOCOL_VERSION => Ok(()),
+            msg => {
+                log::debug!("handshake failed: {:?}", msg);
+                Err(ParseMessageError::UnsupportedVersion.into())
             }
         }
-        log::debug!("recieved: {}", msgstr);
-        Ok(msgstr.parse::<DaemonMessage>()?)
     }
-}
 
-impl Drop for Connection {
-    fn drop(&mut self) { let _ = std::fs::remove_file(Self::socket_path(&self.conf)); }
+    pub fn communicate(&self, msg: ClientMessage) -> Result<DaemonMessage, error::Error> {
+        let mut conn = &self.conn;
+        conn.write_all(&encode(&msg)?)?;
+        let frame = read_frame(&mut conn, MAX_FRAME_LEN)?.ok_or(ParseMessageError::BadFrame)?;
+        log::debug!("msg len, {}", frame.len());
+        let msg = decode::<DaemonMessage>(&frame)?;
+        log::debug!("recieved: {:?}", msg);
+        Ok(msg)
+    }
 }
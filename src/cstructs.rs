@@ -1,4 +1,5 @@
 use crate::buffer::Buffer;
+use crate::structs::ShadowAging;
 use std::io::Error;
 
 #[repr(C)]
@@ -63,8 +64,17 @@ impl Spwd {
         Ok(())
     }
 
-    pub fn pack_args(&mut self, buf: &mut Buffer, name: &str, pass: &str) -> Result<(), Error> {
-        self.pack(buf, name, pass, -1, -1, -1, -1, -1, -1, 0)
+    pub fn pack_args(&mut self, buf: &mut Buffer, name: &str, pass: &str, aging: &ShadowAging) -> Result<(), Error> {
+        self.pack(buf,
+                  name,
+                  pass,
+                  aging.lstchg as libc::c_long,
+                  aging.min as libc::c_long,
+                  aging.max as libc::c_long,
+                  aging.warn as libc::c_long,
+                  aging.inact as libc::c_long,
+                  aging.expire as libc::c_long,
+                  0)
     }
 }
 
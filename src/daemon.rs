@@ -1,151 +1,361 @@
 mod applog;
+mod directory;
+mod enumeration;
 mod error;
+mod fallback;
 mod ghclient;
 mod message;
+mod policy;
+mod securefile;
+mod shadow;
+mod snapshot;
 mod statics;
 mod structs;
 
+use directory::{Directory, DirectoryUser};
+use enumeration::{Database, Enumerations};
 use error::Error;
+use fallback::{FALLBACK_PATH, FALLBACK_VERSION, Fallback, PwEntry};
 use ghclient::GithubClient;
 use message::*;
+use nix::sys::socket::{self as sock, UnixCredentials};
+use shadow::ShadowStore;
 use statics::CONF_PATH;
-use std::collections::hash_map::Entry;
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashSet;
+use std::collections::hash_map::RandomState;
 use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::os::fd::FromRawFd;
 use std::os::unix;
 use std::path::Path;
-use structs::{Config, SocketConfig, UserConfig};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+use structs::{Config, ShadowAging, SocketConfig, UserConfig, epoch_secs};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
 
 #[tokio::main]
 async fn main() {
     applog::init(Some("sectorad"));
-    let mut d = Daemon::new();
+    let d = Arc::new(Daemon::new());
     d.run().await.expect("run");
     log::debug!("Run stopped");
 }
 
+/// Wait before retrying a failed refresh
+const REFRESH_RETRY: Duration = Duration::from_secs(60);
+/// Lower bound between refreshes when `refresh_ahead` leaves no time until the next one
+const REFRESH_MIN_INTERVAL: Duration = Duration::from_secs(10);
+/// Requests are small, so a client cannot make the daemon buffer more than this
+const MAX_REQUEST_LEN: usize = 64 * 1024;
+/// Longest a client may stall in the middle of a conversation
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How often the directory is checked for changes to write to the fallback file
+const FALLBACK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Keeps the snapshot fresh in the background so that requests are answered from memory without waiting for GitHub
+async fn refresh_loop(client: Arc<GithubClient>) {
+    // GitHub outages must not prevent the daemon from starting: NSS/SSH
+    // keep working from cache once the process is up.
+    match client.get_rate_limit().await {
+        Ok(rl) => log::info!("Rate Limit: {:?}", rl),
+        Err(e) => log::warn!("get rate limit failed at start (continuing): {:?}", e),
+    }
+    let max_jitter = client.conf.refresh_jitter;
+    let mut delay = client.refresh_due_in().saturating_sub(jitter(max_jitter));
+    loop {
+        tokio::time::sleep(delay).await;
+        delay = match client.refresh().await {
+            Ok(()) => client.refresh_due_in()
+                            .saturating_sub(jitter(max_jitter))
+                            .max(REFRESH_MIN_INTERVAL),
+            Err(e) => {
+                log::warn!("background refresh failed, retrying: {:?}", e);
+                REFRESH_RETRY + jitter(max_jitter)
+            }
+        };
+        log::debug!("next refresh in {:?}", delay);
+    }
+}
+
+/// Rewrites the fallback file of the NSS module whenever the directory has changed
+async fn fallback_loop(daemon: Arc<Daemon>) {
+    let mut written: Option<Arc<Directory>> = None;
+    loop {
+        let directory = daemon.client.directory();
+        // Without the sectors from GitHub the directory holds the break-glass group alone, and the file left by an
+        // earlier run is still the better answer
+        if directory.has_github_sectors() && !written.as_ref().is_some_and(|w| Arc::ptr_eq(w, &directory)) {
+            match daemon.write_fallback(&directory) {
+                Ok(()) => written = Some(directory),
+                Err(e) => log::warn!("failed to write {}: {:?}", FALLBACK_PATH, e),
+            }
+        }
+        tokio::time::sleep(FALLBACK_INTERVAL).await;
+    }
+}
+
+/// Random duration of up to `max` seconds, drawn from the randomly seeded std hasher
+fn jitter(max: u64) -> Duration {
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(random % (max.saturating_mul(1000) + 1))
+}
+
+/// Who may make a request, checked against the peer credentials of the connection
+enum Access {
+    Anyone,
+    Root,
+}
+
+impl Access {
+    /// Password hashes, the full sector list and anything discarding or replacing state are for root only
+    fn of(msg: &ClientMessage) -> Self {
+        match msg {
+            ClientMessage::Sp(_)
+            | ClientMessage::SectorGroups
+            | ClientMessage::CleanUp
+            | ClientMessage::Deny { .. }
+            | ClientMessage::SnapshotExport { .. }
+            | ClientMessage::SnapshotImport { .. } => Access::Root,
+            ClientMessage::Hello { .. }
+            | ClientMessage::Key { .. }
+            | ClientMessage::Pam { .. }
+            | ClientMessage::Passwd { .. }
+            | ClientMessage::RateLimit
+            | ClientMessage::Status
+            | ClientMessage::Pw(_)
+            | ClientMessage::Gr(_) => Access::Anyone,
+        }
+    }
+}
+
+/// The listening socket passed by systemd socket activation (`LISTEN_FDS`), if any
+fn activated_socket() -> Option<UnixListener> {
+    let mut fds = match sd_notify::listen_fds() {
+        Ok(fds) => fds,
+        Err(e) => {
+            log::warn!("ignoring sockets passed by systemd: {}", e);
+            return None;
+        }
+    };
+    if fds.len() > 1 {
+        log::warn!("{} sockets passed by systemd, listening on the first one only",
+                   fds.len());
+    }
+    let fd = fds.next()?;
+    // SAFETY: systemd passes the descriptor to this process only, and nothing else takes ownership of it
+    let socket = unsafe { unix::net::UnixListener::from_raw_fd(fd) };
+    let stream = sock::getsockopt(&socket, sock::sockopt::SockType).is_ok_and(|t| t == sock::SockType::Stream);
+    let listening = sock::getsockopt(&socket, sock::sockopt::AcceptConn).unwrap_or(false);
+    assert!(stream && listening,
+            "socket passed by systemd must be a listening stream socket (ListenStream=)");
+    socket.set_nonblocking(true).expect("non-blocking socket");
+    Some(UnixListener::from_std(socket).expect("register socket"))
+}
+
+/// Binds the socket in place of a stale one, open to every user since requests are authorized per caller
+fn bind_socket(conf: &SocketConfig) -> UnixListener {
+    if fs::metadata(&conf.socket_path).is_ok() {
+        std::fs::remove_file(&conf.socket_path).expect("remove socket before bind");
+    }
+    let socket = UnixListener::bind(&conf.socket_path).expect("bind socket");
+    fs::set_permissions(&conf.socket_path, unix::fs::PermissionsExt::from_mode(0o666)).unwrap_or_default();
+    socket
+}
+
+/// Async counterpart of `message::read_frame`, bounded by `MAX_REQUEST_LEN`
+async fn read_request(stream: &mut UnixStream) -> io::Result<Option<Vec<u8>>> {
+    let mut prefix = [0u8; 4];
+    match stream.read_exact(&mut prefix).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut frame = frame_buffer(prefix, MAX_REQUEST_LEN)?;
+    stream.read_exact(&mut frame[4..]).await?;
+    Ok(Some(frame))
+}
+
+/// Identity of the peer for the logs
+fn caller(cred: Option<UnixCredentials>) -> String {
+    match cred {
+        Some(cred) => format!("uid:{} gid:{} pid:{}", cred.uid(), cred.gid(), cred.pid()),
+        None => String::from("unknown peer"),
+    }
+}
+
+/// Shared by the tasks serving the clients; nothing here is locked across an `await`
 struct Daemon {
-    client: GithubClient,
+    client: Arc<GithubClient>,
+    shadow: Mutex<ShadowStore>,
     socket_conf: SocketConfig,
-    socket: unix::net::UnixDatagram,
-    msg_cache: HashMap<u32, VecDeque<DaemonMessage>>,
+    socket: UnixListener,
+    /// Passed by systemd, which owns the socket file and keeps listening while the daemon restarts
+    activated: bool,
+    enumerations: Enumerations,
+    connections: AtomicU64,
+    started: Instant,
 }
 
 impl Drop for Daemon {
     fn drop(&mut self) {
         log::debug!("Drop daemon");
-        let _ = fs::remove_file(&self.socket_conf.socket_path);
+        if !self.activated {
+            let _ = fs::remove_file(&self.socket_conf.socket_path);
+        }
     }
 }
 
 impl Daemon {
     fn new() -> Self {
         let config = Config::from_path(&CONF_PATH).expect("valid config");
-        let socket_conf = SocketConfig::new();
-        fs::create_dir_all(&socket_conf.socket_dir).expect("create socket dir");
-        fs::set_permissions(&socket_conf.socket_dir, unix::fs::PermissionsExt::from_mode(0o777)).unwrap_or_default();
-        if fs::metadata(&socket_conf.socket_path).is_ok() {
-            std::fs::remove_file(&socket_conf.socket_path).expect("remove socket before bind");
+        // Everything deciding who may log in lives in directories only the daemon can write to
+        securefile::ensure_dir(Path::new(&config.runtime_dir), 0o755).expect("trusted runtime dir");
+        securefile::ensure_dir(Path::new(&config.cache_dir), 0o700).expect("trusted cache dir");
+        // The state dir also holds nss.json, which every process resolving a name reads; the other files are private
+        for path in [&config.shadow_path, &config.override_path, FALLBACK_PATH] {
+            let dir = Path::new(path).parent().unwrap_or(Path::new("/"));
+            securefile::ensure_dir(dir, 0o755).expect("trusted state dir");
         }
-        let socket = unix::net::UnixDatagram::bind(&socket_conf.socket_path).expect("bind socket");
-        fs::set_permissions(&socket_conf.socket_path, unix::fs::PermissionsExt::from_mode(0o666)).unwrap_or_default();
-        let client = GithubClient::new(&config);
+        let fallback_dir = Path::new(FALLBACK_PATH).parent().unwrap_or(Path::new("/"));
+        if securefile::check_searchable(fallback_dir).is_err() {
+            log::error!("{:?} is closed to other users, who cannot read {} while the daemon is down; make it mode 0755",
+                        fallback_dir,
+                        FALLBACK_PATH);
+        }
+        let socket_conf = SocketConfig::from_runtime_dir(&config.runtime_dir);
+        let (socket, activated) = match activated_socket() {
+            Some(socket) => (socket, true),
+            None => (bind_socket(&socket_conf), false),
+        };
+        let client = Arc::new(GithubClient::new(&config));
+        let shadow = Mutex::new(ShadowStore::new(&config.shadow_path));
         log::debug!("Initialised");
         Daemon { client,
+                 shadow,
                  socket_conf,
                  socket,
-                 msg_cache: HashMap::new() }
+                 activated,
+                 enumerations: Enumerations::default(),
+                 connections: AtomicU64::new(0),
+                 started: Instant::now() }
     }
 
-    async fn run(&mut self) -> Result<(), Error> {
-        // GitHub outages must not prevent the daemon from starting: NSS/SSH
-        // keep working from cache once the process is up.
-        match self.client.get_rate_limit().await {
-            Ok(rl) => log::info!("Rate Limit: {:?}", rl),
-            Err(e) => log::warn!("get rate limit failed at start (continuing): {:?}", e),
-        }
-        match self.client.get_sectors().await {
-            Ok(sectors) => log::info!("{} sector[s] loaded", sectors.len()),
-            Err(e) => log::warn!("get sectors failed at start (will use cache on demand): {:?}", e),
-        }
+    async fn run(self: Arc<Self>) -> Result<(), Error> {
+        // Clients queued on the socket are answered from the stored snapshot while the first refresh runs
+        tokio::spawn(refresh_loop(Arc::clone(&self.client)));
+        tokio::spawn(fallback_loop(Arc::clone(&self)));
+        let daemon = Arc::clone(&self);
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(enumeration::REAP_INTERVAL).await;
+                daemon.enumerations.reap();
+            }
+        });
         let _ = sd_notify::notify(&[sd_notify::NotifyState::Ready]);
-        log::info!("Start running @ {}", &self.socket_conf.socket_path);
+        log::info!("Start running @ {}{}",
+                   &self.socket_conf.socket_path,
+                   if self.activated { " (socket activated)" } else { "" });
         loop {
-            let mut buf = [0u8; 4096];
-            let (recv_cnt, src) = self.socket.recv_from(&mut buf)?;
-            let msgstr = match String::from_utf8(buf[..recv_cnt].to_vec()) {
-                Ok(s) => s,
+            let stream = match self.socket.accept().await {
+                Ok((stream, _)) => stream,
                 Err(e) => {
-                    log::warn!("invalid utf-8 from client: {}", e);
+                    log::warn!("failed to accept a client: {}", e);
                     continue;
                 }
             };
-            log::debug!("recv: {}, src:{:?}", msgstr, src);
-            let client_msg = match msgstr.parse::<ClientMessage>() {
-                Ok(m) => m,
+            let daemon = Arc::clone(&self);
+            let conn = self.connections.fetch_add(1, Ordering::Relaxed);
+            tokio::spawn(async move {
+                daemon.serve(conn, stream).await;
+                daemon.enumerations.close(conn);
+            });
+        }
+    }
+
+    /// Answers the requests of one client until it closes the connection, stalls or sends a broken frame
+    async fn serve(&self, conn: u64, mut stream: UnixStream) {
+        let cred = sock::getsockopt(&stream, sock::sockopt::PeerCredentials).ok();
+        loop {
+            // A process may pause between the entries of an enumeration, `getent passwd | less` for instance
+            let idle = if self.enumerations.is_open(conn) {
+                enumeration::SESSION_TTL
+            } else {
+                CLIENT_TIMEOUT
+            };
+            let frame = match tokio::time::timeout(idle, read_request(&mut stream)).await {
+                Ok(Ok(Some(frame))) => frame,
+                Ok(Ok(None)) => break,
+                Ok(Err(e)) => {
+                    log::warn!("failed to read a request from {}: {}", caller(cred), e);
+                    break;
+                }
+                Err(_) => {
+                    log::debug!("closing idle connection of {}", caller(cred));
+                    break;
+                }
+            };
+            let response = match message::decode::<ClientMessage>(&frame) {
+                Ok(client_msg) => {
+                    log::debug!("recv: {:?}, cred:{:?}", client_msg, cred);
+                    self.handle(&client_msg, conn, cred).await
+                }
                 Err(e) => {
-                    log::warn!("failed to parse client message {:?}: {:?}", msgstr, e);
-                    continue;
+                    log::warn!("failed to decode client message from {}: {:?}", caller(cred), e);
+                    DaemonMessage::Error { message: String::from("bad request") }
                 }
             };
-            let response = self.handle(&client_msg).await;
-            log::debug!("-> response: {}", response);
-
-            let msg = response.to_string();
-            let msgs = message::DividedMessage::new(&msg, 1024);
-            let Some(src_path) = src.as_pathname() else {
-                log::warn!("client address has no pathname: {:?}", src);
-                continue;
+            log::debug!("-> response: {:?}", response);
+            let frame = match message::encode(&response) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    log::warn!("failed to encode response: {:?}", e);
+                    break;
+                }
             };
-            for (i, dividedmsg) in msgs.iter().enumerate() {
-                match self.socket.send_to(dividedmsg.to_string().as_bytes(), src_path) {
-                    Ok(sendsize) => log::debug!("send: {}", sendsize),
-                    Err(err) => log::warn!("failed to send back to the client {:?}:{}", src, err),
+            match tokio::time::timeout(CLIENT_TIMEOUT, stream.write_all(&frame)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => {
+                    log::warn!("failed to send back to the client {}: {}", caller(cred), e);
+                    break;
                 }
-                if i != msgs.len() - 1 {
-                    let mut buf = [0u8; 4096];
-                    let (recv_cnt, _) = match self.socket.recv_from(&mut buf) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            log::warn!("failed to recv cont from client: {}", e);
-                            break;
-                        }
-                    };
-                    let msgstr = match String::from_utf8(buf[..recv_cnt].to_vec()) {
-                        Ok(s) => s,
-                        Err(e) => {
-                            log::warn!("invalid utf-8 cont from client: {}", e);
-                            break;
-                        }
-                    };
-                    let cont = match msgstr.parse::<ClientMessage>() {
-                        Ok(m) => m,
-                        Err(e) => {
-                            log::warn!("failed to parse cont message: {:?}", e);
-                            break;
-                        }
-                    };
-                    match self.handle(&cont).await {
-                        DaemonMessage::Success => continue,
-                        _ => break,
-                    }
+                Err(_) => {
+                    log::warn!("timed out sending back to the client {}", caller(cred));
+                    break;
                 }
             }
         }
     }
 
-    async fn handle(&mut self, msg: &ClientMessage) -> DaemonMessage {
+    async fn handle(&self, msg: &ClientMessage, conn: u64, cred: Option<UnixCredentials>) -> DaemonMessage {
+        if let Access::Root = Access::of(msg)
+           && cred.is_none_or(|cred| cred.uid() != 0)
+        {
+            log::warn!("{:?} denied for {}", msg, caller(cred));
+            return DaemonMessage::Error { message: String::from("permission denied") };
+        }
+        if let Some(response) = self.handle_break_glass(msg, cred) {
+            return response;
+        }
         match msg {
-            ClientMessage::Key { user } => match self.client.get_user_public_keys(user).await {
-                Ok(keys) => DaemonMessage::Key { keys: keys.join("\n") },
-                Err(_) => DaemonMessage::Error { message: String::from("get key failed") },
-            },
-            ClientMessage::Pam { user } => match self.client.check_pam(user).await {
+            ClientMessage::Key { user } => {
+                DaemonMessage::Key { keys: self.client.get_user_public_keys(user).join("\n") }
+            }
+            ClientMessage::Pam { user } => match self.client.check_pam(user) {
                 Ok(result) => DaemonMessage::Pam { result },
                 Err(_) => DaemonMessage::Error { message: String::from("check pam failed") },
             },
-            ClientMessage::CleanUp => match self.client.clear_all_caches().await {
-                Ok(_) => DaemonMessage::Success,
+            ClientMessage::Passwd { pass } => self.handle_passwd(pass, cred),
+            ClientMessage::Deny { user } => self.handle_deny(user, cred),
+            ClientMessage::SnapshotExport { path } => self.handle_snapshot(path, true, cred),
+            ClientMessage::SnapshotImport { path } => self.handle_snapshot(path, false, cred),
+            ClientMessage::CleanUp => match self.client.reset_snapshot() {
+                Ok(_) => match self.client.refresh().await {
+                    Ok(()) => DaemonMessage::Success,
+                    Err(_) => DaemonMessage::Error { message: String::from("refresh after clean up failed") },
+                },
                 Err(_) => DaemonMessage::Error { message: String::from("clean up failed") },
             },
             ClientMessage::RateLimit => match self.client.get_rate_limit().await {
@@ -154,29 +364,77 @@ impl Daemon {
                                                      reset: rl.rate.reset },
                 Err(_) => DaemonMessage::Error { message: String::from("get rate limit failed") },
             },
-            ClientMessage::SectorGroups => match self.client.get_sectors().await {
+            ClientMessage::SectorGroups => match self.client.get_sectors() {
                 Ok(sectors) => DaemonMessage::SectorGroups { sectors },
                 Err(_) => DaemonMessage::Error { message: String::from("get sectors failed") },
             },
-            ClientMessage::Pw(pw) => self.handle_pw(pw).await,
-            ClientMessage::Sp(sp) => self.handle_sp(sp).await,
-            ClientMessage::Gr(gr) => self.handle_gr(gr).await,
-            ClientMessage::Cont => DaemonMessage::Success,
+            ClientMessage::Status => self.status(),
+            ClientMessage::Pw(pw) => self.handle_pw(pw, conn, cred),
+            ClientMessage::Sp(sp) => self.handle_sp(sp, conn, cred),
+            ClientMessage::Gr(gr) => self.handle_gr(gr, conn, cred),
+            ClientMessage::Hello { .. } => DaemonMessage::Hello { version: message::PROTOCOL_VERSION },
         }
     }
 
-    fn get_msg(&mut self, pid: u32) -> DaemonMessage {
-        match self.msg_cache.entry(pid) {
-            Entry::Occupied(mut o) => match o.get_mut().pop_front() {
-                Some(msg) => msg,
-                None => DaemonMessage::Error { message: String::from("not found") },
-            },
-            Entry::Vacant(_) => DaemonMessage::Error { message: String::from("not found") },
+    /// Answers key and PAM requests of break-glass users from the config alone
+    fn handle_break_glass(&self, msg: &ClientMessage, cred: Option<UnixCredentials>) -> Option<DaemonMessage> {
+        match msg {
+            ClientMessage::Key { user } => {
+                let bg = self.client.conf.break_glass_user(user)?;
+                log::error!("BREAK-GLASS: key lookup for {} (requested by {:?})", user, cred);
+                Some(DaemonMessage::Key { keys: bg.keys.join("\n") })
+            }
+            ClientMessage::Pam { user } => {
+                self.client.conf.break_glass_user(user)?;
+                log::error!("BREAK-GLASS: PAM account check for {} (requested by {:?})", user, cred);
+                Some(DaemonMessage::Pam { result: true })
+            }
+            _ => None,
+        }
+    }
+
+    fn status(&self) -> DaemonMessage {
+        let refresh = self.client.refresh_outcome();
+        let directory = self.client.directory();
+        DaemonMessage::Status { uptime: self.started.elapsed().as_secs(),
+                                last_refresh: refresh.succeeded_at,
+                                refresh_error: refresh.error,
+                                snapshot_age: self.client.snapshot_age(),
+                                max_stale: self.client.conf.max_stale,
+                                stale: self.client.is_too_stale(),
+                                sectors: directory.sectors().len(),
+                                members: directory.user_count(),
+                                cache_dir: self.client.conf.cache_dir.clone(),
+                                config_path: CONF_PATH.display().to_string() }
+    }
+
+    fn start_enumeration(&self, conn: u64, db: Database, cred: Option<UnixCredentials>, directory: Arc<Directory>)
+                         -> DaemonMessage {
+        match self.enumerations.start(conn, db, cred, directory) {
+            Ok(()) => DaemonMessage::Success,
+            Err(_) => DaemonMessage::Error { message: String::from("too many enumerations") },
         }
     }
 
-    fn clear_cache(&mut self, pid: u32) -> DaemonMessage {
-        self.msg_cache.remove(&pid).unwrap_or_default();
+    /// Entry at the position of the session, built only now so that sessions hold no more than a cursor
+    fn next_entry(&self, conn: u64, db: Database) -> DaemonMessage {
+        let Some((directory, position)) = self.enumerations.next(conn, db) else {
+            return DaemonMessage::Error { message: String::from("not found") };
+        };
+        let entry = match db {
+            Database::Passwd => directory.membership(position)
+                                         .map(|(user, gid)| self.pw_entry(user, gid)),
+            Database::Shadow => directory.membership(position)
+                                         .map(|(user, _)| self.sp_entry(user, &directory)),
+            Database::Group => directory.sectors()
+                                        .get(position)
+                                        .map(|sector| DaemonMessage::Gr { sector: sector.clone() }),
+        };
+        entry.unwrap_or(DaemonMessage::Error { message: String::from("not found") })
+    }
+
+    fn end_enumeration(&self, conn: u64, db: Database) -> DaemonMessage {
+        self.enumerations.end(conn, db);
         DaemonMessage::Success
     }
 
@@ -199,127 +457,168 @@ impl Daemon {
         (home, sh)
     }
 
-    fn get_pass(&self, login: &str) -> String {
-        let home = self.client.conf.home.replace("{}", login);
-        let pass: String = match UserConfig::from_path(&Path::new(&home).join(&self.client.conf.user_conf_path)) {
-            Ok(personal) => match personal.pass {
-                Some(pass) => pass,
-                None => String::from("*"),
-            },
-            Err(_) => String::from("*"),
-        };
-        pass
+    fn get_shadow(&self, user: &DirectoryUser, directory: &Directory) -> (String, ShadowAging) {
+        let conf = &self.client.conf;
+        let entry = self.shadow
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .get(&user.member.login);
+        let max = directory.sectors_of(user).filter_map(|s| conf.max_days(s)).min();
+        // Expire only when every sector granting access to the member is time-limited
+        let expire = directory.sectors_of(user)
+                              .map(|s| conf.expire(s))
+                              .collect::<Option<Vec<i64>>>()
+                              .and_then(|days| days.into_iter().max());
+        let aging = ShadowAging { lstchg: entry.as_ref().and_then(|e| e.lstchg).unwrap_or(-1),
+                                  max: max.unwrap_or(-1),
+                                  expire: expire.unwrap_or(-1),
+                                  ..ShadowAging::default() };
+        (entry.map(|e| e.pass).unwrap_or(String::from("*")), aging)
     }
 
-    async fn handle_pw(&mut self, pw: &Pw) -> DaemonMessage {
-        match pw {
-            Pw::Uid(uid) => {
-                for sector in self.client.get_sectors().await.unwrap_or_default() {
-                    for member in sector.members.values() {
-                        if uid == &member.id {
-                            let (home, sh) = self.get_home_sh(&member.login);
-                            return DaemonMessage::Pw { login: member.login.clone(),
-                                                       uid: *uid,
-                                                       gid: sector.get_gid(),
-                                                       home,
-                                                       sh };
-                        }
-                    }
-                }
-            }
-            Pw::Nam(name) => {
-                for sector in self.client.get_sectors().await.unwrap_or_default() {
-                    for member in sector.members.values() {
-                        if name == &member.login {
-                            let (home, sh) = self.get_home_sh(&member.login);
-                            return DaemonMessage::Pw { login: member.login.clone(),
-                                                       uid: member.id,
-                                                       gid: sector.get_gid(),
-                                                       home,
-                                                       sh };
-                        }
-                    }
-                }
+    fn handle_passwd(&self, pass: &str, cred: Option<UnixCredentials>) -> DaemonMessage {
+        let Some(cred) = cred else {
+            log::warn!("passwd request without credentials");
+            return DaemonMessage::Error { message: String::from("no credentials") };
+        };
+        let uid = u64::from(cred.uid());
+        let login = self.client.directory().user_by_uid(uid).map(|u| u.member.login.clone());
+        let Some(login) = login else {
+            log::warn!("passwd request from non-member uid:{} pid:{}", cred.uid(), cred.pid());
+            return DaemonMessage::Error { message: String::from("not a member") };
+        };
+        let updated = self.shadow
+                          .lock()
+                          .unwrap_or_else(PoisonError::into_inner)
+                          .set(&login, pass);
+        match updated {
+            Ok(_) => {
+                log::info!("password hash updated for {} (pid:{})", login, cred.pid());
+                DaemonMessage::Success
             }
-            Pw::Ent(Ent::Set(pid)) => {
-                let mut ents = VecDeque::new();
-                for sector in self.client.get_sectors().await.unwrap_or_default() {
-                    for member in sector.members.values() {
-                        let (home, sh) = self.get_home_sh(&member.login);
-                        let pw = DaemonMessage::Pw { login: member.login.clone(),
-                                                     uid: member.id,
-                                                     gid: sector.get_gid(),
-                                                     home,
-                                                     sh };
-                        ents.push_back(pw);
-                    }
-                }
-                self.msg_cache.insert(*pid, ents).unwrap_or_default();
-                return DaemonMessage::Success;
+            Err(e) => {
+                log::warn!("failed to update password hash for {}: {:?}", login, e);
+                DaemonMessage::Error { message: String::from("passwd failed") }
             }
-            Pw::Ent(Ent::Get(pid)) => return self.get_msg(*pid),
-            Pw::Ent(Ent::End(pid)) => return self.clear_cache(*pid),
         }
-        DaemonMessage::Error { message: String::from("not found") }
     }
 
-    async fn handle_sp(&mut self, sp: &Sp) -> DaemonMessage {
-        match sp {
-            Sp::Nam(name) => {
-                for sector in self.client.get_sectors().await.unwrap_or_default() {
-                    if let Some(member) = sector.members.get(name) {
-                        let pass = self.get_pass(name);
-                        return DaemonMessage::Sp { login: member.login.clone(),
-                                                   pass };
-                    }
-                }
+    fn handle_deny(&self, user: &str, cred: Option<UnixCredentials>) -> DaemonMessage {
+        match self.client.deny_user(user) {
+            Ok(_) => {
+                log::warn!("{} denied by {}", user, caller(cred));
+                DaemonMessage::Success
             }
-            Sp::Ent(Ent::Set(pid)) => {
-                let mut ents = VecDeque::new();
-                for sector in self.client.get_sectors().await.unwrap_or_default() {
-                    for member in sector.members.values() {
-                        let pass = self.get_pass(&member.login);
-                        let sp = DaemonMessage::Sp { login: member.login.clone(),
-                                                     pass };
-                        ents.push_back(sp);
-                    }
-                }
-                self.msg_cache.insert(*pid, ents).unwrap_or_default();
-                return DaemonMessage::Success;
+            Err(e) => {
+                log::warn!("failed to deny {}: {:?}", user, e);
+                DaemonMessage::Error { message: String::from("deny failed") }
             }
-            Sp::Ent(Ent::Get(pid)) => return self.get_msg(*pid),
-            Sp::Ent(Ent::End(pid)) => return self.clear_cache(*pid),
         }
-        DaemonMessage::Error { message: String::from("not found") }
     }
 
-    async fn handle_gr(&mut self, gr: &Gr) -> DaemonMessage {
-        match gr {
-            Gr::Gid(gid) => {
-                for sector in self.client.get_sectors().await.unwrap_or_default() {
-                    if gid == &sector.get_gid() {
-                        return DaemonMessage::Gr { sector };
-                    }
-                }
+    /// Exports or imports a signed snapshot at `path`, which is read or written by the daemon
+    fn handle_snapshot(&self, path: &str, export: bool, cred: Option<UnixCredentials>) -> DaemonMessage {
+        let action = if export { "export" } else { "import" };
+        let path = Path::new(path);
+        let result = if export {
+            self.client.export_snapshot(path)
+        } else {
+            self.client.import_snapshot(path)
+        };
+        match result {
+            Ok(_) => {
+                log::info!("snapshot {} {:?} by {}", action, path, caller(cred));
+                DaemonMessage::Success
             }
-            Gr::Nam(name) => {
-                for sector in self.client.get_sectors().await.unwrap_or_default() {
-                    if name == &sector.get_group() {
-                        return DaemonMessage::Gr { sector };
-                    }
-                }
+            Err(e) => {
+                log::warn!("snapshot {} {:?} failed: {:?}", action, path, e);
+                DaemonMessage::Error { message: format!("snapshot {} failed: {:?}", action, e) }
             }
-            Gr::Ent(Ent::Set(pid)) => {
-                let mut ents = VecDeque::new();
-                for sector in self.client.get_sectors().await.unwrap_or_default() {
-                    ents.push_back(DaemonMessage::Gr { sector });
+        }
+    }
+
+    fn pw_entry(&self, user: &DirectoryUser, gid: u64) -> DaemonMessage { self.passwd_entry(user, gid).into() }
+
+    fn sp_entry(&self, user: &DirectoryUser, directory: &Directory) -> DaemonMessage {
+        let (pass, aging) = self.get_shadow(user, directory);
+        DaemonMessage::Sp { login: user.member.login.clone(),
+                            pass,
+                            aging }
+    }
+
+    fn passwd_entry(&self, user: &DirectoryUser, gid: u64) -> PwEntry {
+        let (home, sh) = self.get_home_sh(&user.member.login);
+        PwEntry { login: user.member.login.clone(),
+                  uid: user.member.id,
+                  gid,
+                  home,
+                  sh }
+    }
+
+    /// Writes the entries `getpwnam`, `getgrgid` and the like would return, each user once with its primary group
+    fn write_fallback(&self, directory: &Directory) -> Result<(), Error> {
+        let mut logins = HashSet::new();
+        let passwd = directory.sectors()
+                              .iter()
+                              .flat_map(|sector| sector.members.values())
+                              .filter(|member| logins.insert(member.login.as_str()))
+                              .filter_map(|member| directory.user_by_login(&member.login))
+                              .map(|user| self.passwd_entry(user, directory.primary_gid(user)))
+                              .collect();
+        let fallback = Fallback { version: FALLBACK_VERSION,
+                                  written_at: epoch_secs(SystemTime::now()),
+                                  passwd,
+                                  group: directory.sectors().to_vec() };
+        securefile::write_atomic(Path::new(FALLBACK_PATH), &serde_json::to_vec(&fallback)?, 0o644)?;
+        log::debug!("wrote {} user[s] and {} group[s] to {}",
+                    fallback.passwd.len(),
+                    fallback.group.len(),
+                    FALLBACK_PATH);
+        Ok(())
+    }
+
+    fn handle_pw(&self, pw: &Pw, conn: u64, cred: Option<UnixCredentials>) -> DaemonMessage {
+        let directory = self.client.directory();
+        let user = match pw {
+            Pw::Uid(uid) => directory.user_by_uid(*uid),
+            Pw::Nam(name) => directory.user_by_login(name),
+            Pw::Ent(Ent::Set) => return self.start_enumeration(conn, Database::Passwd, cred, directory),
+            Pw::Ent(Ent::Get) => return self.next_entry(conn, Database::Passwd),
+            Pw::Ent(Ent::End) => return self.end_enumeration(conn, Database::Passwd),
+        };
+        match user {
+            Some(user) => self.pw_entry(user, directory.primary_gid(user)),
+            None => DaemonMessage::Error { message: String::from("not found") },
+        }
+    }
+
+    fn handle_sp(&self, sp: &Sp, conn: u64, cred: Option<UnixCredentials>) -> DaemonMessage {
+        let directory = self.client.directory();
+        match sp {
+            Sp::Nam(name) => {
+                if let Some(user) = directory.user_by_login(name) {
+                    return self.sp_entry(user, &directory);
                 }
-                self.msg_cache.insert(*pid, ents).unwrap_or_default();
-                return DaemonMessage::Success;
             }
-            Gr::Ent(Ent::Get(pid)) => return self.get_msg(*pid),
-            Gr::Ent(Ent::End(pid)) => return self.clear_cache(*pid),
+            Sp::Ent(Ent::Set) => return self.start_enumeration(conn, Database::Shadow, cred, directory),
+            Sp::Ent(Ent::Get) => return self.next_entry(conn, Database::Shadow),
+            Sp::Ent(Ent::End) => return self.end_enumeration(conn, Database::Shadow),
         }
         DaemonMessage::Error { message: String::from("not found") }
     }
+
+    fn handle_gr(&self, gr: &Gr, conn: u64, cred: Option<UnixCredentials>) -> DaemonMessage {
+        let directory = self.client.directory();
+        let sector = match gr {
+            Gr::Gid(gid) => directory.group_by_gid(*gid),
+            Gr::Nam(name) => directory.group_by_name(name),
+            Gr::Ent(Ent::Set) => return self.start_enumeration(conn, Database::Group, cred, Arc::clone(&directory)),
+            Gr::Ent(Ent::Get) => return self.next_entry(conn, Database::Group),
+            Gr::Ent(Ent::End) => return self.end_enumeration(conn, Database::Group),
+        };
+        match sector {
+            Some(sector) => DaemonMessage::Gr { sector: sector.clone() },
+            None => DaemonMessage::Error { message: String::from("not found") },
+        }
+    }
 }
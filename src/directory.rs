@@ -0,0 +1,81 @@
+use crate::structs::{Member, Sector, SectorGroup};
+use std::collections::HashMap;
+
+/// A user of the directory with the sectors granting access, in the order of the sectors
+#[derive(Debug, Clone)]
+pub struct DirectoryUser {
+    pub member: Member,
+    /// Indices into `Directory::sectors`; the first one gives the primary group
+    sectors: Vec<usize>,
+}
+
+/// Sectors indexed by uid, login, gid and group name so that lookups do not scan all members
+#[derive(Debug, Default)]
+pub struct Directory {
+    sectors: Vec<SectorGroup>,
+    users: HashMap<String, DirectoryUser>,
+    uids: HashMap<u64, String>,
+    gids: HashMap<u64, usize>,
+    groups: HashMap<String, usize>,
+    /// Sector index and login of every membership, in the order passwd and shadow enumerations list them
+    memberships: Vec<(usize, String)>,
+    /// Whether the sectors from GitHub are in it, rather than the break-glass group alone
+    from_github: bool,
+}
+
+impl Directory {
+    /// Builds the indices; on duplicate uids, gids and group names the first sector wins, as with a linear scan
+    pub fn new(sectors: Vec<SectorGroup>, from_github: bool) -> Self {
+        let mut directory = Directory { from_github,
+                                        ..Directory::default() };
+        for (idx, sector) in sectors.iter().enumerate() {
+            directory.gids.entry(sector.get_gid()).or_insert(idx);
+            directory.groups.entry(sector.get_group()).or_insert(idx);
+            for member in sector.members.values() {
+                directory.memberships.push((idx, member.login.clone()));
+                directory.uids.entry(member.id).or_insert_with(|| member.login.clone());
+                directory.users
+                         .entry(member.login.clone())
+                         .or_insert_with(|| DirectoryUser { member: member.clone(),
+                                                            sectors: Vec::new() })
+                         .sectors
+                         .push(idx);
+            }
+        }
+        directory.sectors = sectors;
+        directory
+    }
+
+    pub fn sectors(&self) -> &[SectorGroup] { &self.sectors }
+
+    pub fn has_github_sectors(&self) -> bool { self.from_github }
+
+    /// Number of distinct members across the sectors
+    pub fn user_count(&self) -> usize { self.users.len() }
+
+    pub fn user_by_login(&self, login: &str) -> Option<&DirectoryUser> { self.users.get(login) }
+
+    pub fn user_by_uid(&self, uid: u64) -> Option<&DirectoryUser> {
+        self.uids.get(&uid).and_then(|login| self.users.get(login))
+    }
+
+    pub fn group_by_gid(&self, gid: u64) -> Option<&SectorGroup> { self.gids.get(&gid).map(|&idx| &self.sectors[idx]) }
+
+    pub fn group_by_name(&self, name: &str) -> Option<&SectorGroup> {
+        self.groups.get(name).map(|&idx| &self.sectors[idx])
+    }
+
+    /// User and gid of the `index`th membership, one entry of a passwd or shadow enumeration
+    pub fn membership(&self, index: usize) -> Option<(&DirectoryUser, u64)> {
+        let (sector, login) = self.memberships.get(index)?;
+        Some((self.users.get(login)?, self.sectors[*sector].get_gid()))
+    }
+
+    /// Gid of the first sector of the user
+    pub fn primary_gid(&self, user: &DirectoryUser) -> u64 { self.sectors[user.sectors[0]].get_gid() }
+
+    /// Sectors granting access to the user
+    pub fn sectors_of<'a>(&'a self, user: &'a DirectoryUser) -> impl Iterator<Item = &'a Sector> {
+        user.sectors.iter().map(|&idx| &self.sectors[idx].sector)
+    }
+}
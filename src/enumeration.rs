@@ -0,0 +1,201 @@
+use crate::directory::Directory;
+use crate::error::Error;
+use nix::sys::socket::UnixCredentials;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Sessions unused for longer are reaped, and their connections closed
+pub const SESSION_TTL: Duration = Duration::from_secs(600);
+/// How often abandoned sessions are looked for
+pub const REAP_INTERVAL: Duration = Duration::from_secs(60);
+/// Open sessions of all users; each keeps the directory it started on alive
+const MAX_SESSIONS: usize = 1024;
+/// Open sessions of a user other than root, so that one user cannot take all of them
+const MAX_SESSIONS_PER_UID: usize = 16;
+
+/// Database of an enumeration; a client may enumerate each of them at once on a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Database {
+    Passwd,
+    Shadow,
+    Group,
+}
+
+/// Position of one `set*ent` in the directory it started on. Only the connection that started it can reach it, as
+/// sessions are keyed by connection.
+struct Session {
+    owner: Option<UnixCredentials>,
+    last_used: Instant,
+    directory: Arc<Directory>,
+    position: usize,
+}
+
+impl Session {
+    fn is_expired(&self, now: Instant) -> bool { now.duration_since(self.last_used) > SESSION_TTL }
+}
+
+/// Enumeration sessions of all connections
+#[derive(Default)]
+pub struct Enumerations {
+    sessions: Mutex<HashMap<(u64, Database), Session>>,
+    reaped: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl Enumerations {
+    fn sessions(&self) -> MutexGuard<'_, HashMap<(u64, Database), Session>> {
+        self.sessions.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Starts a session in place of the one of the connection for `db`, unless the limits are reached
+    pub fn start(&self, conn: u64, db: Database, owner: Option<UnixCredentials>, directory: Arc<Directory>)
+                 -> Result<(), Error> {
+        self.start_at(conn, db, owner, directory, Instant::now())
+    }
+
+    fn start_at(&self, conn: u64, db: Database, owner: Option<UnixCredentials>, directory: Arc<Directory>,
+                now: Instant)
+                -> Result<(), Error> {
+        let mut sessions = self.sessions();
+        sessions.remove(&(conn, db));
+        let expired = sessions.len();
+        sessions.retain(|_, s| !s.is_expired(now));
+        self.reaped
+            .fetch_add((expired - sessions.len()) as u64, Ordering::Relaxed);
+        let uid = owner.map(|cred| cred.uid());
+        let of_uid = sessions.values()
+                             .filter(|s| s.owner.map(|cred| cred.uid()) == uid)
+                             .count();
+        if sessions.len() >= MAX_SESSIONS || (uid != Some(0) && of_uid >= MAX_SESSIONS_PER_UID) {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            log::warn!("{:?} enumeration refused for {:?}: {} session(s) open, {} of the caller",
+                       db,
+                       owner,
+                       sessions.len(),
+                       of_uid);
+            return Err(Error::Limit);
+        }
+        sessions.insert((conn, db),
+                        Session { owner,
+                                  last_used: now,
+                                  directory,
+                                  position: 0 });
+        Ok(())
+    }
+
+    /// Directory of the session and the position of its next entry, `None` when the session is missing or expired
+    pub fn next(&self, conn: u64, db: Database) -> Option<(Arc<Directory>, usize)> {
+        let mut sessions = self.sessions();
+        let session = sessions.get_mut(&(conn, db))?;
+        session.last_used = Instant::now();
+        session.position += 1;
+        Some((Arc::clone(&session.directory), session.position - 1))
+    }
+
+    pub fn end(&self, conn: u64, db: Database) { self.sessions().remove(&(conn, db)); }
+
+    /// Drops the sessions of a closed connection
+    pub fn close(&self, conn: u64) { self.sessions().retain(|(id, _), _| *id != conn); }
+
+    pub fn is_open(&self, conn: u64) -> bool { self.sessions().keys().any(|(id, _)| *id == conn) }
+
+    /// Drops the sessions unused for longer than `SESSION_TTL`
+    pub fn reap(&self) { self.reap_at(Instant::now()) }
+
+    fn reap_at(&self, now: Instant) {
+        let mut sessions = self.sessions();
+        let before = sessions.len();
+        sessions.retain(|_, s| !s.is_expired(now));
+        let reaped = (before - sessions.len()) as u64;
+        let total = self.reaped.fetch_add(reaped, Ordering::Relaxed) + reaped;
+        log::debug!("enumeration sessions: {} open, {} reaped now, {} reaped and {} refused in total",
+                    sessions.len(),
+                    reaped,
+                    total,
+                    self.rejected.load(Ordering::Relaxed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cred(uid: u32) -> Option<UnixCredentials> {
+        Some(UnixCredentials::from(libc::ucred { pid: 1000 + uid as i32,
+                                                 uid,
+                                                 gid: uid }))
+    }
+
+    fn directory() -> Arc<Directory> { Arc::new(Directory::default()) }
+
+    #[test]
+    fn next_walks_the_directory_the_session_started_on() {
+        let enumerations = Enumerations::default();
+        let directory = directory();
+        enumerations.start(1, Database::Passwd, cred(1000), Arc::clone(&directory))
+                    .unwrap();
+        for expected in 0..3 {
+            let (dir, position) = enumerations.next(1, Database::Passwd).unwrap();
+            assert!(Arc::ptr_eq(&dir, &directory));
+            assert_eq!(position, expected);
+        }
+        assert!(enumerations.next(1, Database::Group).is_none());
+        assert!(enumerations.next(2, Database::Passwd).is_none());
+    }
+
+    #[test]
+    fn sessions_expire_after_the_ttl() {
+        let enumerations = Enumerations::default();
+        let start = Instant::now();
+        enumerations.start_at(1, Database::Passwd, cred(1000), directory(), start)
+                    .unwrap();
+        enumerations.reap_at(start + SESSION_TTL / 2);
+        assert!(enumerations.is_open(1));
+        enumerations.reap_at(start + SESSION_TTL + Duration::from_secs(1));
+        assert!(!enumerations.is_open(1));
+        assert!(enumerations.next(1, Database::Passwd).is_none());
+        assert_eq!(enumerations.reaped.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn sessions_are_limited_per_uid_except_for_root() {
+        let enumerations = Enumerations::default();
+        let start = Instant::now();
+        for conn in 0..MAX_SESSIONS_PER_UID as u64 {
+            enumerations.start_at(conn, Database::Passwd, cred(1000), directory(), start)
+                        .unwrap();
+        }
+        let over = MAX_SESSIONS_PER_UID as u64;
+        assert!(matches!(enumerations.start_at(over, Database::Passwd, cred(1000), directory(), start),
+                         Err(Error::Limit)));
+        // Restarting an open session does not take another one
+        enumerations.start_at(0, Database::Passwd, cred(1000), directory(), start)
+                    .unwrap();
+        enumerations.start_at(over, Database::Passwd, cred(1001), directory(), start)
+                    .unwrap();
+        for conn in 100..100 + over + 1 {
+            enumerations.start_at(conn, Database::Group, cred(0), directory(), start)
+                        .unwrap();
+        }
+        assert_eq!(enumerations.rejected.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn sessions_are_limited_in_total_until_reaped() {
+        let enumerations = Enumerations::default();
+        let start = Instant::now();
+        for conn in 0..MAX_SESSIONS as u64 {
+            enumerations.start_at(conn, Database::Group, cred(conn as u32), directory(), start)
+                        .unwrap();
+        }
+        let over = MAX_SESSIONS as u64;
+        assert!(matches!(enumerations.start_at(over, Database::Group, cred(0), directory(), start),
+                         Err(Error::Limit)));
+        let later = start + SESSION_TTL + Duration::from_secs(1);
+        enumerations.start_at(over, Database::Group, cred(0), directory(), later)
+                    .unwrap();
+        assert_eq!(enumerations.reaped.load(Ordering::Relaxed), MAX_SESSIONS as u64);
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(Debug)]
+use std::fmt;
+
+#[derive(Debug, Clone)]
 pub enum Error {
     Serde,
     Io,
@@ -7,7 +9,49 @@ pub enum Error {
     /// HTTP transport, DNS, TLS, or non-success status from GitHub API
     Http,
     /// URL parse / request construction failure
+    #[allow(dead_code)]
     Request,
+    /// File not owned by the daemon or accessible by other users
+    #[allow(dead_code)]
+    Permission,
+    /// Password hash rejected by the shadow store
+    #[allow(dead_code)]
+    InvalidPass,
+    /// Nothing has been fetched from GitHub yet
+    #[allow(dead_code)]
+    NotReady,
+    /// Snapshot signature missing, invalid or not verifiable
+    Signature,
+    /// Imported snapshot too old, not newer than the loaded one, or from another source
+    #[allow(dead_code)]
+    StaleSnapshot,
+    /// Too many enumerations open, in total or by the caller
+    #[allow(dead_code)]
+    Limit,
+    /// No daemon socket, or nothing listening on it
+    #[allow(dead_code)]
+    NotRunning,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let msg = match self {
+            Error::Serde => "invalid JSON",
+            Error::Io => "I/O error",
+            Error::Toml => "invalid TOML",
+            Error::ParseMsg => "malformed message",
+            Error::Http => "GitHub request failed",
+            Error::Request => "invalid GitHub request",
+            Error::Permission => "file owned by another user or accessible by others",
+            Error::InvalidPass => "invalid password hash",
+            Error::NotReady => "nothing fetched from GitHub yet",
+            Error::Signature => "missing or invalid snapshot signature",
+            Error::StaleSnapshot => "snapshot too old or from another source",
+            Error::Limit => "too many enumerations",
+            Error::NotRunning => "daemon not running",
+        };
+        f.write_str(msg)
+    }
 }
 
 impl From<serde_json::Error> for Error {
@@ -19,9 +63,18 @@ impl From<std::io::Error> for Error {
 impl From<toml::de::Error> for Error {
     fn from(_err: toml::de::Error) -> Error { Error::Toml }
 }
+impl From<toml::ser::Error> for Error {
+    fn from(_err: toml::ser::Error) -> Error { Error::Toml }
+}
+impl From<nix::errno::Errno> for Error {
+    fn from(_err: nix::errno::Errno) -> Error { Error::Io }
+}
 impl From<reqwest::Error> for Error {
     fn from(_err: reqwest::Error) -> Error { Error::Http }
 }
+impl From<openssl::error::ErrorStack> for Error {
+    fn from(_err: openssl::error::ErrorStack) -> Error { Error::Signature }
+}
 
 #[derive(Debug)]
 pub enum ParseSectorTypeError {
@@ -44,8 +97,11 @@ pub enum ParseSectorGroupError {
 
 #[derive(Debug)]
 pub enum ParseMessageError {
-    ParseClientMessageError,
-    ParseDaemonMessageError,
+    /// Missing or inconsistent length prefix, or a frame beyond `MAX_FRAME_LEN`
+    BadFrame,
+    UnsupportedVersion,
+    /// The frame does not hold a message of the expected kind
+    BadMessage,
 }
 
 impl From<ParseMessageError> for Error {
@@ -0,0 +1,40 @@
+use crate::message::DaemonMessage;
+use crate::structs::SectorGroup;
+use serde::{Deserialize, Serialize};
+
+/// Where the daemon leaves its passwd and group entries, kept across reboots so that names resolve even when the
+/// daemon does not come up
+pub const FALLBACK_PATH: &str = "/var/lib/sectora/nss.json";
+/// Bumped on every incompatible change of `Fallback`
+pub const FALLBACK_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PwEntry {
+    pub login: String,
+    pub uid: u64,
+    pub gid: u64,
+    pub home: String,
+    pub sh: String,
+}
+
+impl From<PwEntry> for DaemonMessage {
+    fn from(entry: PwEntry) -> Self {
+        DaemonMessage::Pw { login: entry.login,
+                            uid: entry.uid,
+                            gid: entry.gid,
+                            home: entry.home,
+                            sh: entry.sh }
+    }
+}
+
+/// Passwd and group entries written by the daemon (`sectorad`) and read by the NSS module (`nssfallback`) to resolve
+/// names while the daemon is down. The file is readable by everyone, so it holds nothing `getent passwd` and
+/// `getent group` would not show.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Fallback {
+    pub version: u32,
+    /// Seconds since the epoch when the daemon wrote the file
+    pub written_at: i64,
+    pub passwd: Vec<PwEntry>,
+    pub group: Vec<SectorGroup>,
+}
@@ -1,15 +1,150 @@
+use crate::directory::Directory;
 use crate::error::Error;
-use crate::structs::{Config, Member, PublicKey, RateLimit, Repo, Sector, SectorGroup, Team};
-use glob::glob;
+use crate::securefile;
+use crate::snapshot::{SignedSnapshot, Snapshot, Source, UserKeys};
+use crate::policy::Window;
+use crate::structs::{Config, Member, Repo, Sector, SectorGroup, Team, epoch_secs};
 use reqwest::{Client, Method, Request, Url, header};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::prelude::*;
-use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::task::{JoinError, JoinSet};
+
+/// Names of system and cloud image accounts that a GitHub login must never shadow
+const RESERVED_USERS: &[&str] = &["root",
+                                  "admin",
+                                  "administrator",
+                                  "ubuntu",
+                                  "debian",
+                                  "centos",
+                                  "ec2-user",
+                                  "fedora",
+                                  "daemon",
+                                  "bin",
+                                  "sys",
+                                  "sync",
+                                  "games",
+                                  "man",
+                                  "lp",
+                                  "mail",
+                                  "news",
+                                  "uucp",
+                                  "proxy",
+                                  "www-data",
+                                  "backup",
+                                  "list",
+                                  "irc",
+                                  "gnats",
+                                  "nobody",
+                                  "operator",
+                                  "sshd",
+                                  "messagebus",
+                                  "syslog"];
+
+const LOCAL_PASSWD_PATH: &str = "/etc/passwd";
+/// Bounds the memory spent on names probed by scanners
+const MAX_UNKNOWN_USERS: usize = 10_000;
+
+/// Read directly instead of through NSS, which would ask this daemon again
+fn local_users() -> HashSet<String> {
+    match std::fs::read_to_string(LOCAL_PASSWD_PATH) {
+        Ok(contents) => contents.lines()
+                                .filter_map(|l| l.split(':').next())
+                                .filter(|name| !name.is_empty() && !name.starts_with('#'))
+                                .map(String::from)
+                                .collect(),
+        Err(e) => {
+            log::warn!("failed to read {}: {}", LOCAL_PASSWD_PATH, e);
+            HashSet::new()
+        }
+    }
+}
 
 pub struct GithubClient {
     client: Client,
     pub conf: Config,
+    hostname: String,
+    snapshot: Mutex<Option<Snapshot>>,
+    /// Bumped on every change of the snapshot or the overrides
+    generation: AtomicU64,
+    directory: Mutex<Option<CachedDirectory>>,
+    /// Names without access and when they were looked up
+    unknown_users: Mutex<HashMap<String, Instant>>,
+    warned: Mutex<HashSet<String>>,
+    /// Number of finished refreshes, to tell whether one finished while waiting for `last_refresh`
+    refreshes: AtomicU64,
+    /// Held while refreshing, with the outcome of the last refresh for the callers that waited on it
+    last_refresh: tokio::sync::Mutex<Result<(), Error>>,
+    /// Readable while a refresh is in flight, unlike `last_refresh`
+    refresh_outcome: Mutex<RefreshOutcome>,
+    /// Last override file that could be read, used while a later edit cannot
+    last_overrides: Mutex<Option<Overrides>>,
+}
+
+/// How the refreshes have gone so far, for `status`
+#[derive(Debug, Clone, Default)]
+pub struct RefreshOutcome {
+    /// Seconds since the epoch when a refresh last succeeded
+    pub succeeded_at: Option<i64>,
+    /// Why the last refresh failed, `None` when it succeeded
+    pub error: Option<String>,
+}
+
+/// What a directory was built from besides the config
+#[derive(PartialEq)]
+struct DirectoryStamp {
+    generation: u64,
+    overrides: Option<(i64, i64)>,
+    passwd: Option<(i64, i64)>,
+}
+
+struct CachedDirectory {
+    stamp: DirectoryStamp,
+    /// When a sector or grant window opens or closes next
+    valid_until: Option<i64>,
+    directory: Arc<Directory>,
+}
+
+type KeysResult = Result<(String, Result<Vec<String>, Error>), JoinError>;
+
+fn collect_keys(result: KeysResult, fetched: &mut Vec<(String, Vec<String>)>) {
+    match result {
+        Ok((login, Ok(keys))) => fetched.push((login, keys)),
+        Ok((login, Err(e))) => log::warn!("fetch of keys for {} failed, keeping the old ones: {:?}", login, e),
+        Err(e) => log::warn!("key fetch task failed: {}", e),
+    }
+}
+
+/// Status change time, which also moves when the mode or owner is fixed and the file becomes readable again
+fn changed(path: &str) -> Option<(i64, i64)> { std::fs::metadata(path).map(|m| (m.ctime(), m.ctime_nsec())).ok() }
+
+/// Local access overrides maintained by the daemon (`sectora deny`)
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Overrides {
+    #[serde(default)]
+    pub deny_users: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PublicKey {
+    pub id: u64,
+    pub key: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Rate {
+    pub limit: usize,
+    pub remaining: usize,
+    pub reset: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RateLimit {
+    pub rate: Rate,
 }
 
 impl GithubClient {
@@ -31,35 +166,32 @@ impl GithubClient {
                                       .timeout(Duration::from_secs(30))
                                       .build()
                                       .expect("build HTTP client");
+        let hostname = nix::unistd::gethostname().ok()
+                                                 .and_then(|h| h.into_string().ok())
+                                                 .unwrap_or_default();
+        log::debug!("hostname: {}, tags: {:?}", hostname, config.host_tags);
+        let snapshot = match Snapshot::load(&Snapshot::path(config)) {
+            Ok(snapshot) => Some(snapshot),
+            Err(e) => {
+                log::info!("no usable snapshot: {:?}", e);
+                None
+            }
+        };
         GithubClient { client,
-                       conf: config.clone() }
-    }
-
-    fn get_cache_path(&self, url: &str) -> std::path::PathBuf {
-        let mut path = std::path::PathBuf::default();
-        path.push(&self.conf.cache_dir);
-        path.push(url);
-        path
-    }
-
-    fn load_contents_from_cache(&self, url: &str) -> Result<(std::fs::Metadata, String), Error> {
-        let path = self.get_cache_path(url);
-        let metadata = std::fs::metadata(path.to_str().unwrap())?;
-        let mut f = File::open(path.to_str().unwrap())?;
-        let mut contents = String::default();
-        f.read_to_string(&mut contents)?;
-        Ok((metadata, contents))
-    }
-
-    fn store_contents_to_cache(&self, url: &str, contents: &str) -> Result<(), Error> {
-        let path = self.get_cache_path(url);
-        std::fs::create_dir_all(path.parent().unwrap_or(std::path::Path::new("/")))?;
-        let mut f = File::create(path.to_str().unwrap())?;
-        f.write_all(contents.as_bytes())?;
-        Ok(())
+                       conf: config.clone(),
+                       hostname,
+                       snapshot: Mutex::new(snapshot),
+                       generation: AtomicU64::new(0),
+                       directory: Mutex::new(None),
+                       unknown_users: Mutex::new(HashMap::new()),
+                       warned: Mutex::new(HashSet::new()),
+                       refreshes: AtomicU64::new(0),
+                       last_refresh: tokio::sync::Mutex::new(Ok(())),
+                       refresh_outcome: Mutex::new(RefreshOutcome::default()),
+                       last_overrides: Mutex::new(None) }
     }
 
-    async fn get_contents_from_url(&self, url: &str) -> Result<String, Error> {
+    async fn get_contents(&self, url: &str) -> Result<String, Error> {
         let mut all_contents: Vec<serde_json::value::Value> = Vec::new();
         let mut page = 1;
         loop {
@@ -70,9 +202,7 @@ impl GithubClient {
             all_contents.append(&mut new_array);
             page += 1;
         }
-        let contents = serde_json::ser::to_string(&all_contents)?;
-        self.store_contents_to_cache(url, &contents)?;
-        Ok(contents)
+        Ok(serde_json::ser::to_string(&all_contents)?)
     }
 
     fn build_request(&self, url: &str) -> Result<Request, Error> {
@@ -114,46 +244,377 @@ impl GithubClient {
         })
     }
 
-    async fn get_contents(&self, url: &str) -> Result<String, Error> {
-        match self.load_contents_from_cache(url) {
-            Ok((metadata, cache_contents)) => match std::time::SystemTime::now().duration_since(metadata.modified()?) {
-                Ok(caching_duration) => {
-                    if caching_duration.as_secs() > self.conf.cache_duration {
-                        match self.get_contents_from_url(url).await {
-                            Ok(contents_from_url) => Ok(contents_from_url),
-                            Err(e) => {
-                                log::warn!("refresh failed for {}, using stale cache: {:?}", url, e);
-                                Ok(cache_contents)
-                            }
-                        }
-                    } else {
-                        Ok(cache_contents)
-                    }
-                }
-                Err(_) => Ok(cache_contents),
-            },
-            Err(_) => self.get_contents_from_url(url).await,
+    /// Whether data fetched at `fetched_at` expires before the next refresh
+    fn is_due(&self, fetched_at: i64) -> bool {
+        let age = epoch_secs(SystemTime::now()) - fetched_at;
+        age + self.conf.refresh_ahead as i64 >= self.conf.cache_duration as i64
+    }
+
+    /// Cached keys of `user`, unless they are older than `max_stale`
+    fn cached_keys(&self, user: &str) -> Option<Vec<String>> {
+        let guard = self.snapshot.lock().ok()?;
+        let keys = guard.as_ref()?.keys.get(user)?;
+        match self.conf.max_stale {
+            Some(max) if epoch_secs(SystemTime::now()) - keys.fetched_at > max as i64 => None,
+            _ => Some(keys.keys.clone()),
+        }
+    }
+
+    /// Seconds since the sectors of the snapshot were fetched, `None` without a snapshot
+    pub fn snapshot_age(&self) -> Option<i64> {
+        let guard = self.snapshot.lock().ok()?;
+        Some(epoch_secs(SystemTime::now()) - guard.as_ref()?.built_at)
+    }
+
+    pub fn refresh_outcome(&self) -> RefreshOutcome {
+        self.refresh_outcome.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    /// Whether the snapshot is older than `max_stale` and no longer grants access
+    pub fn is_too_stale(&self) -> bool {
+        match (self.conf.max_stale, self.snapshot_age()) {
+            (Some(max), Some(age)) => age > max as i64,
+            _ => false,
+        }
+    }
+
+    fn update_snapshot(&self, update: impl FnOnce(&mut Option<Snapshot>)) {
+        let Ok(mut guard) = self.snapshot.lock() else { return };
+        update(&mut guard);
+        if let Some(snapshot) = guard.as_ref()
+           && let Err(e) = snapshot.store(&Snapshot::path(&self.conf))
+        {
+            log::warn!("failed to store snapshot: {:?}", e);
         }
     }
 
-    pub async fn get_user_public_keys(&self, user: &str) -> Result<Vec<String>, Error> {
+    /// Replaces the sectors and the refreshed keys, dropping keys of users who are no longer members
+    fn store_refresh(&self, sectors: &[SectorGroup], refreshed: Vec<(String, Vec<String>)>) {
+        let source = Source::new(&self.conf);
+        let now = epoch_secs(SystemTime::now());
+        self.update_snapshot(|snapshot| {
+                let mut keys = snapshot.take()
+                                       .filter(|s| s.source == source)
+                                       .map(|s| s.keys)
+                                       .unwrap_or_default();
+                keys.retain(|login, _| sectors.iter().any(|s| s.members.contains_key(login)));
+                keys.extend(refreshed.into_iter()
+                                     .map(|(login, keys)| (login, UserKeys { fetched_at: now, keys })));
+                let mut fresh = Snapshot::new(source, sectors.to_vec(), now);
+                fresh.keys = keys;
+                *snapshot = Some(fresh);
+            });
+        self.sectors_changed();
+    }
+
+    /// Invalidates the directory and the unknown users built from the previous sectors
+    fn sectors_changed(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        if let Ok(mut unknown) = self.unknown_users.lock() {
+            unknown.clear();
+        }
+    }
+
+    /// Keys from the snapshot only, so that a login never waits for GitHub; `refresh` is the only place fetching them
+    pub fn get_user_public_keys(&self, user: &str) -> Vec<String> {
+        if !self.is_member(user) {
+            return Vec::new();
+        }
+        if self.is_too_stale() {
+            log::warn!("snapshot is older than max_stale, no keys returned for {}", user);
+            return Vec::new();
+        }
+        match self.cached_keys(user) {
+            Some(keys) => keys,
+            None => {
+                // Only when the prefetch failed for this user, or has not succeeded within max_stale
+                log::warn!("no keys of {} in the snapshot, none returned until a refresh", user);
+                Vec::new()
+            }
+        }
+    }
+
+    async fn fetch_user_public_keys(&self, user: &str) -> Result<Vec<String>, Error> {
         let url = format!("{}/users/{}/keys", self.conf.endpoint, user);
         let contents = self.get_contents(&url).await?;
         let keys = serde_json::from_str::<Vec<PublicKey>>(&contents)?;
         Ok(keys.iter().map(|k| k.key.clone()).collect())
     }
 
-    pub async fn check_pam(&self, user: &str) -> Result<bool, Error> {
-        let sectors = self.get_sectors().await?;
-        Ok(sectors.iter().any(|team| team.members.contains_key(user)))
+    pub fn check_pam(&self, user: &str) -> Result<bool, Error> {
+        if self.is_too_stale() {
+            log::warn!("snapshot is older than max_stale, access denied to {}", user);
+            return Ok(false);
+        }
+        Ok(self.directory().user_by_login(user).is_some())
+    }
+
+    pub fn get_sectors(&self) -> Result<Vec<SectorGroup>, Error> { self.filter_access(self.get_all_sectors()?) }
+
+    /// Sectors accessible on this host followed by the break-glass group, rebuilt only when the snapshot, the
+    /// overrides or the local passwd file change, or a sector or grant window opens or closes
+    pub fn directory(&self) -> Arc<Directory> {
+        let now = epoch_secs(SystemTime::now());
+        let stamp = DirectoryStamp { generation: self.generation.load(Ordering::Acquire),
+                                     overrides: changed(&self.conf.override_path),
+                                     passwd: changed(LOCAL_PASSWD_PATH) };
+        let Ok(mut guard) = self.directory.lock() else {
+            return Arc::new(self.build_directory());
+        };
+        if let Some(cached) = guard.as_ref()
+           && cached.stamp == stamp
+           && cached.valid_until.is_none_or(|t| now < t)
+        {
+            return Arc::clone(&cached.directory);
+        }
+        let directory = Arc::new(self.build_directory());
+        *guard = Some(CachedDirectory { stamp,
+                                        valid_until: self.conf.next_window_change(now),
+                                        directory: Arc::clone(&directory) });
+        directory
+    }
+
+    fn build_directory(&self) -> Directory {
+        let (mut sectors, from_github) = match self.get_sectors() {
+            Ok(sectors) => (sectors, true),
+            Err(e) => {
+                log::warn!("get sectors failed: {:?}", e);
+                (Vec::new(), false)
+            }
+        };
+        sectors.extend(self.conf.break_glass_sector());
+        log::debug!("directory rebuilt with {} sector[s]", sectors.len());
+        Directory::new(sectors, from_github)
+    }
+
+    /// Sectors from the snapshot; `refresh` is the only place fetching them from GitHub
+    fn get_all_sectors(&self) -> Result<Vec<SectorGroup>, Error> {
+        let guard = self.snapshot.lock().map_err(|_| Error::NotReady)?;
+        guard.as_ref().map(|s| s.sectors.clone()).ok_or(Error::NotReady)
+    }
+
+    /// Refreshes the snapshot, or waits for the refresh in flight and shares its outcome, so that concurrent callers
+    /// never fetch everything from GitHub more than once
+    pub async fn refresh(self: &Arc<Self>) -> Result<(), Error> {
+        let finished = self.refreshes.load(Ordering::Acquire);
+        let mut last = self.last_refresh.lock().await;
+        if self.refreshes.load(Ordering::Acquire) != finished {
+            log::debug!("joined the refresh in flight");
+            return last.clone();
+        }
+        *last = self.fetch_refresh().await;
+        self.refreshes.fetch_add(1, Ordering::AcqRel);
+        let mut outcome = self.refresh_outcome.lock().unwrap_or_else(PoisonError::into_inner);
+        match last.as_ref() {
+            Ok(()) => {
+                outcome.succeeded_at = Some(epoch_secs(SystemTime::now()));
+                outcome.error = None;
+            }
+            Err(e) => outcome.error = Some(e.to_string()),
+        }
+        last.clone()
+    }
+
+    /// Fetches the sectors and the keys of every member with access whose keys are missing or due, and stores them
+    /// as the new snapshot so that key lookups never wait for GitHub. Keys failing to refresh are kept as they are.
+    async fn fetch_refresh(self: &Arc<Self>) -> Result<(), Error> {
+        let sectors = self.fetch_sectors().await?;
+        // The overrides only narrow down whose keys to prefetch, so the sectors are stored even when they fail
+        let members: HashSet<String> = match self.filter_access(sectors.clone()) {
+            Ok(accessible) => accessible.into_iter().flat_map(|s| s.members.into_keys()).collect(),
+            Err(e) => {
+                log::warn!("prefetching no keys until the overrides can be read: {:?}", e);
+                HashSet::new()
+            }
+        };
+        let due: Vec<String> = match self.snapshot.lock() {
+            Ok(guard) => {
+                let keys = guard.as_ref().map(|s| &s.keys);
+                members.into_iter()
+                       .filter(|login| keys.and_then(|k| k.get(login)).is_none_or(|k| self.is_due(k.fetched_at)))
+                       .collect()
+            }
+            Err(_) => Vec::new(),
+        };
+        let refreshed = self.fetch_keys_of(due).await;
+        log::debug!("refreshed {} sector[s] and keys of {} user[s]",
+                    sectors.len(),
+                    refreshed.len());
+        self.store_refresh(&sectors, refreshed);
+        Ok(())
+    }
+
+    /// Fetches the keys of `logins` with at most `key_fetch_concurrency` requests at a time
+    async fn fetch_keys_of(self: &Arc<Self>, logins: Vec<String>) -> Vec<(String, Vec<String>)> {
+        let mut tasks = JoinSet::new();
+        let mut fetched = Vec::new();
+        for login in logins {
+            if tasks.len() >= self.conf.key_fetch_concurrency.max(1)
+               && let Some(result) = tasks.join_next().await
+            {
+                collect_keys(result, &mut fetched);
+            }
+            let client = Arc::clone(self);
+            tasks.spawn(async move {
+                     let keys = client.fetch_user_public_keys(&login).await;
+                     (login, keys)
+                 });
+        }
+        while let Some(result) = tasks.join_next().await {
+            collect_keys(result, &mut fetched);
+        }
+        fetched
+    }
+
+    /// Time until the snapshot should be refreshed, zero when it is missing or built from another source
+    pub fn refresh_due_in(&self) -> Duration {
+        let Ok(guard) = self.snapshot.lock() else {
+            return Duration::ZERO;
+        };
+        match guard.as_ref() {
+            Some(s) if s.source == Source::new(&self.conf) => {
+                let age = epoch_secs(SystemTime::now()) - s.built_at;
+                let due = self.conf.cache_duration.saturating_sub(self.conf.refresh_ahead) as i64 - age;
+                Duration::from_secs(due.max(0) as u64)
+            }
+            _ => Duration::ZERO,
+        }
     }
 
-    pub async fn get_sectors(&self) -> Result<Vec<SectorGroup>, Error> {
+    async fn fetch_sectors(&self) -> Result<Vec<SectorGroup>, Error> {
         let mut sectors: Vec<SectorGroup> = self.get_teams_result().await?;
         sectors.append(&mut self.get_repos_result().await?);
         Ok(sectors)
     }
 
+    /// Drops sectors not applying to this host, reserved and denied members, and sectors and members whose
+    /// `not_before`/`not_after` window does not contain the current time
+    fn filter_access(&self, sectors: Vec<SectorGroup>) -> Result<Vec<SectorGroup>, Error> {
+        let now = epoch_secs(SystemTime::now());
+        let overrides = self.overrides()?;
+        let local_users = local_users();
+        Ok(sectors.into_iter()
+                  .filter(|sector| self.conf.applies_to_host(&sector.sector, &self.hostname))
+                  .filter(|sector| {
+                      let window = self.conf.sector_window(&sector.sector);
+                      self.warn_expiry(&format!("sector {}", sector.sector.name), &window, now);
+                      window.contains(now)
+                  })
+                  .map(|mut sector| {
+                      sector.members.retain(|login, _| {
+                                        if self.is_reserved(login, &local_users) || !self.is_allowed(login, &overrides)
+                                        {
+                                            return false;
+                                        }
+                                        let window = self.conf.grant_window(login);
+                                        self.warn_expiry(&format!("grant for {}", login), &window, now);
+                                        window.contains(now)
+                                    });
+                      sector
+                  })
+                  .collect())
+    }
+
+    fn is_allowed(&self, login: &str, overrides: &Overrides) -> bool {
+        let login = String::from(login);
+        !self.conf.deny_users.contains(&login)
+        && !overrides.deny_users.contains(&login)
+        && (self.conf.allow_only_users.is_empty() || self.conf.allow_only_users.contains(&login))
+    }
+
+    /// Overrides to apply, the last ones read when the file has become unreadable and none at all when it never was,
+    /// so that a broken file cannot lift a denial
+    fn overrides(&self) -> Result<Overrides, Error> {
+        let mut last = self.last_overrides.lock().unwrap_or_else(PoisonError::into_inner);
+        match self.load_overrides() {
+            Ok(overrides) => {
+                *last = Some(overrides.clone());
+                Ok(overrides)
+            }
+            Err(e) => {
+                log::warn!("failed to load overrides {}: {:?}", self.conf.override_path, e);
+                last.clone().ok_or(e)
+            }
+        }
+    }
+
+    /// Reads the override file on every call so that edits take effect without a restart
+    fn load_overrides(&self) -> Result<Overrides, Error> {
+        let path = Path::new(&self.conf.override_path);
+        if !path.exists() {
+            return Ok(Overrides::default());
+        }
+        Ok(toml::from_str::<Overrides>(&securefile::read_private(path)?)?)
+    }
+
+    /// Refuses to write when the file cannot be read, which would drop the denials already in it
+    pub fn deny_user(&self, login: &str) -> Result<(), Error> {
+        let mut overrides = self.load_overrides()?;
+        if !overrides.deny_users.iter().any(|u| u == login) {
+            overrides.deny_users.push(String::from(login));
+        }
+        let contents = toml::to_string(&overrides)?;
+        securefile::write_atomic(Path::new(&self.conf.override_path), contents.as_bytes(), 0o600)?;
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    fn warn_expiry(&self, name: &str, window: &Window, now: i64) {
+        let Some(not_after) = window.not_after else { return };
+        if now >= not_after || not_after - now > self.conf.expiry_warning as i64 {
+            return;
+        }
+        if self.first_warning(&format!("expiry of {}@{}", name, not_after)) {
+            log::warn!("{} expires in {} seconds", name, not_after - now);
+        }
+    }
+
+    /// Keeps repeated lookups from flooding the log with the same warning
+    fn first_warning(&self, key: &str) -> bool {
+        match self.warned.lock() {
+            Ok(mut warned) => warned.insert(String::from(key)),
+            Err(_) => false,
+        }
+    }
+
+    /// True for built-in and configured reserved names, break-glass logins and accounts in the local passwd file
+    fn is_reserved(&self, login: &str, local_users: &HashSet<String>) -> bool {
+        let reserved = RESERVED_USERS.contains(&login)
+                       || self.conf.reserved_users.iter().any(|u| u == login)
+                       || self.conf.break_glass_user(login).is_some()
+                       || local_users.contains(login);
+        if reserved && self.first_warning(&format!("reserved {}", login)) {
+            log::warn!("refusing GitHub user {}: the name is reserved or a local account", login);
+        }
+        reserved
+    }
+
+    /// Whether `user` may log in to this host; other names are remembered for `negative_cache_duration` so that
+    /// repeated lookups by scanners cost neither API calls nor disk writes
+    fn is_member(&self, user: &str) -> bool {
+        let now = Instant::now();
+        let ttl = Duration::from_secs(self.conf.negative_cache_duration);
+        if let Ok(unknown) = self.unknown_users.lock()
+           && unknown.get(user).is_some_and(|at| now.duration_since(*at) < ttl)
+        {
+            log::debug!("{} is a cached unknown user", user);
+            return false;
+        }
+        if self.directory().user_by_login(user).is_some() {
+            return true;
+        }
+        log::info!("{} has no access to this host, no keys returned", user);
+        if let Ok(mut unknown) = self.unknown_users.lock() {
+            if unknown.len() >= MAX_UNKNOWN_USERS {
+                unknown.retain(|_, at| now.duration_since(*at) < ttl);
+            }
+            if unknown.len() < MAX_UNKNOWN_USERS {
+                unknown.insert(String::from(user), now);
+            }
+        }
+        false
+    }
+
     async fn get_teams_result(&self) -> Result<Vec<SectorGroup>, Error> {
         let gh_teams = self.get_team_map(&self.conf.org).await?;
         let mut teams = Vec::new();
@@ -237,24 +698,46 @@ impl GithubClient {
         })
     }
 
-    pub async fn clear_all_caches(&self) -> Result<(), Error> {
-        let mut path = self.get_cache_path("");
-        path.push("**/*");
-        let pattern = path.to_str().ok_or(Error::Io)?;
-        let entries = glob(pattern).map_err(|e| {
-            log::warn!("cache glob failed: {}", e);
-            Error::Io
-        })?;
-        for entry in entries {
-            match entry {
-                Ok(path) => {
-                    if path.is_file() {
-                        std::fs::remove_file(path)?
-                    }
+    /// Signs the snapshot with `snapshot.signing_key` and writes it to `path`
+    pub fn export_snapshot(&self, path: &Path) -> Result<(), Error> {
+        let key = self.conf.snapshot.signing_key.as_ref().ok_or(Error::Signature)?;
+        let signed = {
+            let guard = self.snapshot.lock().map_err(|_| Error::NotReady)?;
+            SignedSnapshot::sign(guard.as_ref().ok_or(Error::NotReady)?, Path::new(key))?
+        };
+        securefile::write_atomic(path, serde_json::to_string(&signed)?.as_bytes(), 0o600)
+    }
+
+    /// Replaces the snapshot with the one exported to `path` once its signature verifies with
+    /// `snapshot.verify_key`. Snapshots older than `snapshot.max_age`, not newer than the loaded one, or built from
+    /// another org, teams or repos are refused.
+    pub fn import_snapshot(&self, path: &Path) -> Result<(), Error> {
+        let key = self.conf.snapshot.verify_key.as_ref().ok_or(Error::Signature)?;
+        let signed = serde_json::from_str::<SignedSnapshot>(&std::fs::read_to_string(path)?)?;
+        let snapshot = signed.verify(Path::new(key))?;
+        let source = Source::new(&self.conf);
+        let now = epoch_secs(SystemTime::now());
+        let mut result = Ok(());
+        self.update_snapshot(|current| {
+                result = snapshot.check_import(&source, self.conf.snapshot.max_age, now, current.as_ref());
+                if result.is_ok() {
+                    *current = Some(snapshot);
                 }
-                Err(e) => log::warn!("cache glob entry error: {:?}", e),
-            }
+            });
+        if result.is_err() {
+            log::warn!("refused snapshot {:?}", path);
         }
+        result?;
+        self.sectors_changed();
         Ok(())
     }
+
+    /// Drops the snapshot including all keys; lookups fail until the next `refresh`
+    pub fn reset_snapshot(&self) -> Result<(), Error> {
+        if let Ok(mut guard) = self.snapshot.lock() {
+            *guard = None;
+        }
+        self.sectors_changed();
+        Snapshot::remove(&Snapshot::path(&self.conf))
+    }
 }
@@ -3,19 +3,26 @@ mod buffer;
 mod connection;
 mod cstructs;
 mod error;
+mod fallback;
 mod message;
+mod nssfallback;
 mod structs;
 
 use buffer::Buffer;
 use connection::Connection;
 use cstructs::{Group, Passwd, Spwd};
+use error::Error;
 use message::{ClientMessage as CMsg, DaemonMessage as DMsg, Ent, Gr, Pw, Sp};
 use nix::errno::Errno;
+use nssfallback::Entries;
+use std::collections::VecDeque;
 use std::ffi::CStr;
+use std::path::Path;
 use std::process;
 use std::string::String;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use structs::SocketConfig;
 
-#[allow(dead_code)]
 enum NssStatus {
     TryAgain,
     Unavail,
@@ -34,6 +41,139 @@ impl From<NssStatus> for libc::c_int {
     }
 }
 
+/// How a failure to get an answer is reported: a daemon that is not running is unavailable, so that nsswitch.conf
+/// actions such as `[UNAVAIL=return]` apply, while one that does not answer in time may be retried
+fn status_of(err: &Error) -> (NssStatus, Errno) {
+    match err {
+        Error::NotRunning => (NssStatus::Unavail, Errno::ENOENT),
+        _ => (NssStatus::TryAgain, Errno::EAGAIN),
+    }
+}
+
+/// Fallback file of the daemon, for the lookups it can answer while it is not running; a daemon that is busy or speaks
+/// another protocol is answered with `TRYAGAIN` instead, so that no stale entry replaces its answer
+fn fallback(err: &Error) -> Option<Arc<Entries>> {
+    if !matches!(err, Error::NotRunning) {
+        return None;
+    }
+    match nssfallback::load() {
+        Ok(entries) => {
+            log::debug!("daemon not running, reading {:?}", nssfallback::path());
+            Some(entries)
+        }
+        Err(e) => {
+            log::debug!("daemon not running, no fallback in {:?}: {:?}", nssfallback::path(), e);
+            None
+        }
+    }
+}
+
+/// Answer of the daemon, or of the fallback file to passwd and group lookups when the daemon is not running
+fn ask(logid: &str, msg: CMsg) -> Result<DMsg, Error> {
+    match Connection::new(logid) {
+        Ok(conn) => conn.communicate(msg),
+        Err(e) => fallback(&e).and_then(|f| f.answer(&msg)).ok_or(e),
+    }
+}
+
+/// Where the entries of an enumeration come from
+enum Cursor {
+    /// The daemon keeps the position of an enumeration per connection
+    Daemon(Connection),
+    Fallback(VecDeque<DMsg>),
+}
+
+/// Cursor held from `set*ent` to `end*ent`
+struct Enumeration {
+    pid: u32,
+    cursor: Cursor,
+    /// Entry that did not fit in the buffer, returned again when glibc retries with a larger one
+    pending: Option<DMsg>,
+}
+
+/// One enumeration per database and process, as glibc serializes the `*ent` calls of a database
+static PW_ENT: Mutex<Option<Enumeration>> = Mutex::new(None);
+static SP_ENT: Mutex<Option<Enumeration>> = Mutex::new(None);
+static GR_ENT: Mutex<Option<Enumeration>> = Mutex::new(None);
+
+fn lock(slot: &Mutex<Option<Enumeration>>) -> MutexGuard<'_, Option<Enumeration>> {
+    slot.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Starts an enumeration on a new connection, or over the fallback file when the daemon is not running
+fn start_ent(logid: &str, msg: fn(Ent) -> CMsg) -> Result<(Cursor, DMsg), Error> {
+    match Connection::new(logid) {
+        Ok(conn) => {
+            let reply = conn.communicate(msg(Ent::Set))?;
+            Ok((Cursor::Daemon(conn), reply))
+        }
+        Err(e) => match fallback(&e).and_then(|f| f.entries(&msg(Ent::Set))) {
+            Some(entries) => Ok((Cursor::Fallback(entries), DMsg::Success)),
+            None => Err(e),
+        },
+    }
+}
+
+/// Starts an enumeration, replacing the one of an earlier `set*ent`
+fn set_ent(slot: &Mutex<Option<Enumeration>>, logid: &str, msg: fn(Ent) -> CMsg) -> Result<DMsg, Error> {
+    let mut enumeration = lock(slot);
+    *enumeration = None;
+    let (cursor, reply) = start_ent(logid, msg)?;
+    *enumeration = Some(Enumeration { pid: process::id(),
+                                      cursor,
+                                      pending: None });
+    Ok(reply)
+}
+
+/// Next entry of the enumeration, which is started when missing or inherited from the parent process
+fn get_ent(slot: &Mutex<Option<Enumeration>>, logid: &str, msg: fn(Ent) -> CMsg) -> Result<DMsg, Error> {
+    let mut enumeration = lock(slot);
+    let pid = process::id();
+    if let Some(e) = enumeration.as_mut().filter(|e| e.pid == pid)
+       && let Some(reply) = e.pending.take()
+    {
+        return Ok(reply);
+    }
+    let mut cursor = match enumeration.take() {
+        Some(e) if e.pid == pid => e.cursor,
+        _ => start_ent(logid, msg)?.0,
+    };
+    let reply = match &mut cursor {
+        Cursor::Daemon(conn) => conn.communicate(msg(Ent::Get))?,
+        Cursor::Fallback(entries) => entries.pop_front()
+                                            .unwrap_or(DMsg::Error { message: String::from("not found") }),
+    };
+    *enumeration = Some(Enumeration { pid,
+                                      cursor,
+                                      pending: None });
+    Ok(reply)
+}
+
+/// Keeps an entry that did not fit in the buffer for the next `get*ent_r`, so that no entry is skipped
+fn unget_ent(slot: &Mutex<Option<Enumeration>>, reply: DMsg) {
+    if let Some(e) = lock(slot).as_mut().filter(|e| e.pid == process::id()) {
+        e.pending = Some(reply);
+    }
+}
+
+/// Ends the enumeration and closes its connection
+fn end_ent(slot: &Mutex<Option<Enumeration>>, msg: fn(Ent) -> CMsg) -> Result<DMsg, Error> {
+    match lock(slot).take() {
+        Some(Enumeration { pid,
+                           cursor: Cursor::Daemon(conn),
+                           .. })
+            if pid == process::id() =>
+        {
+            conn.communicate(msg(Ent::End))
+        }
+        _ => Ok(DMsg::Success),
+    }
+}
+
+/// Whether an entry failed to pack because of its contents, such as a NUL byte, rather than the size of the buffer;
+/// glibc answers ERANGE with a larger buffer, which would never help
+fn malformed(err: &std::io::Error) -> bool { err.kind() == std::io::ErrorKind::InvalidData }
+
 fn string_from(cstrptr: *const libc::c_char) -> String {
     let cstr: &CStr = unsafe { CStr::from_ptr(cstrptr) };
     String::from(cstr.to_str().unwrap_or(""))
@@ -64,8 +204,8 @@ macro_rules! try_unwrap {
                 ret
             }
             Err(e) => {
-                log::debug!("failed (will retry): {:?}", e);
-                return libc::c_int::from(NssStatus::TryAgain);
+                log::debug!("failed: {:?}", e);
+                return libc::c_int::from(status_of(&e).0);
             }
         }
     }};
@@ -73,16 +213,23 @@ macro_rules! try_unwrap {
         match $getter {
             Ok(ret) => ret,
             Err(e) => {
-                log::debug!("failed (will retry): {:?}", e);
-                unsafe {
-                    *$err_no_p = Errno::EAGAIN as libc::c_int;
-                }
-                return libc::c_int::from(NssStatus::TryAgain);
+                log::debug!("failed: {:?}", e);
+                let (status, errno) = status_of(&e);
+                fail!($err_no_p, errno, status)
             }
         }
     }};
 }
 
+/// Points the module at the socket of a test daemon. It is not exported from the shared object, and the exported
+/// functions never take a path from the environment of the process they are loaded into.
+#[doc(hidden)]
+pub fn set_test_runtime_dir(dir: &Path) { SocketConfig::set_test_runtime_dir(&dir.to_string_lossy()) }
+
+/// Points the module at a fallback file written by the user running the tests, which it then trusts in place of root
+#[doc(hidden)]
+pub fn set_test_fallback_path(path: &Path) { nssfallback::set_test_path(path) }
+
 /// # Safety
 ///
 /// This function intended to be called from nss
@@ -92,8 +239,8 @@ pub unsafe extern "C" fn _nss_sectora_getpwnam_r(cnameptr: *const libc::c_char,
                                                  errnop: *mut libc::c_int)
                                                  -> libc::c_int {
     let mut buffer = Buffer::new(buf, buflen);
-    let conn = try_unwrap!(Connection::new("_nss_sectora_getpwnam_r"), errnop);
-    let msg = try_unwrap!(conn.communicate(CMsg::Pw(Pw::Nam(string_from(cnameptr)))), errnop);
+    let msg = try_unwrap!(ask("_nss_sectora_getpwnam_r", CMsg::Pw(Pw::Nam(string_from(cnameptr)))),
+                          errnop);
     if let DMsg::Pw { login,
                       uid,
                       gid,
@@ -102,6 +249,7 @@ pub unsafe extern "C" fn _nss_sectora_getpwnam_r(cnameptr: *const libc::c_char,
     {
         match unsafe { (*pwptr).pack_args(&mut buffer, &login, uid, gid, &home, &sh) } {
             Ok(_) => succeed!(),
+            Err(e) if malformed(&e) => fail!(errnop, Errno::ENOENT, NssStatus::NotFound),
             Err(_) => fail!(errnop, Errno::ERANGE, NssStatus::TryAgain),
         }
     }
@@ -116,8 +264,7 @@ pub unsafe extern "C" fn _nss_sectora_getpwuid_r(uid: libc::uid_t, pwptr: *mut P
                                                  buflen: libc::size_t, errnop: *mut libc::c_int)
                                                  -> libc::c_int {
     let mut buffer = Buffer::new(buf, buflen);
-    let conn = try_unwrap!(Connection::new("_nss_sectora_getpwuid_r"), errnop);
-    let msg = try_unwrap!(conn.communicate(CMsg::Pw(Pw::Uid(uid as u64))), errnop);
+    let msg = try_unwrap!(ask("_nss_sectora_getpwuid_r", CMsg::Pw(Pw::Uid(uid as u64))), errnop);
     if let DMsg::Pw { login,
                       uid,
                       gid,
@@ -126,6 +273,7 @@ pub unsafe extern "C" fn _nss_sectora_getpwuid_r(uid: libc::uid_t, pwptr: *mut P
     {
         match unsafe { (*pwptr).pack_args(&mut buffer, &login, uid, gid, &home, &sh) } {
             Ok(_) => succeed!(),
+            Err(e) if malformed(&e) => fail!(errnop, Errno::ENOENT, NssStatus::NotFound),
             Err(_) => fail!(errnop, Errno::ERANGE, NssStatus::TryAgain),
         }
     }
@@ -137,8 +285,7 @@ pub unsafe extern "C" fn _nss_sectora_getpwuid_r(uid: libc::uid_t, pwptr: *mut P
 /// This function intended to be called from nss
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn _nss_sectora_setpwent() -> libc::c_int {
-    let conn = try_unwrap!(Connection::new("_nss_sectora_setpwent"));
-    let msg = try_unwrap!(conn.communicate(CMsg::Pw(Pw::Ent(Ent::Set(process::id())))));
+    let msg = try_unwrap!(set_ent(&PW_ENT, "_nss_sectora_setpwent", |ent| CMsg::Pw(Pw::Ent(ent))));
     if let DMsg::Success = msg {
         return libc::c_int::from(NssStatus::Success);
     }
@@ -152,21 +299,31 @@ pub unsafe extern "C" fn _nss_sectora_setpwent() -> libc::c_int {
 pub unsafe extern "C" fn _nss_sectora_getpwent_r(pwptr: *mut Passwd, buf: *mut libc::c_char, buflen: libc::size_t,
                                                  errnop: *mut libc::c_int)
                                                  -> libc::c_int {
-    let mut buffer = Buffer::new(buf, buflen);
-    let conn = try_unwrap!(Connection::new("_nss_sectora_getpwent_r"), errnop);
-    let msg = try_unwrap!(conn.communicate(CMsg::Pw(Pw::Ent(Ent::Get(process::id())))), errnop);
-    if let DMsg::Pw { login,
-                      uid,
-                      gid,
-                      home,
-                      sh, } = msg
-    {
-        match unsafe { (*pwptr).pack_args(&mut buffer, &login, uid, gid, &home, &sh) } {
-            Ok(_) => succeed!(),
-            Err(_) => fail!(errnop, Errno::ERANGE, NssStatus::TryAgain),
+    // An entry that can never be returned is skipped, so that the enumeration goes on past it
+    loop {
+        let mut buffer = Buffer::new(buf, buflen);
+        let msg = try_unwrap!(get_ent(&PW_ENT, "_nss_sectora_getpwent_r", |ent| CMsg::Pw(Pw::Ent(ent))),
+                              errnop);
+        if let DMsg::Pw { login,
+                          uid,
+                          gid,
+                          home,
+                          sh, } = &msg
+        {
+            match unsafe { (*pwptr).pack_args(&mut buffer, login, *uid, *gid, home, sh) } {
+                Ok(_) => succeed!(),
+                Err(e) if malformed(&e) => {
+                    log::warn!("skipping an entry that cannot be returned: {:?}", e);
+                    continue;
+                }
+                Err(_) => {
+                    unget_ent(&PW_ENT, msg);
+                    fail!(errnop, Errno::ERANGE, NssStatus::TryAgain)
+                }
+            }
         }
+        fail!(errnop, Errno::ENOENT, NssStatus::NotFound)
     }
-    fail!(errnop, Errno::ENOENT, NssStatus::NotFound)
 }
 
 /// # Safety
@@ -174,8 +331,7 @@ pub unsafe extern "C" fn _nss_sectora_getpwent_r(pwptr: *mut Passwd, buf: *mut l
 /// This function intended to be called from nss
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn _nss_sectora_endpwent() -> libc::c_int {
-    let conn = try_unwrap!(Connection::new("_nss_sectora_endpwent"));
-    let msg = try_unwrap!(conn.communicate(CMsg::Pw(Pw::Ent(Ent::End(process::id())))));
+    let msg = try_unwrap!(end_ent(&PW_ENT, |ent| CMsg::Pw(Pw::Ent(ent))));
     if let DMsg::Success = msg {
         return libc::c_int::from(NssStatus::Success);
     }
@@ -193,9 +349,10 @@ pub unsafe extern "C" fn _nss_sectora_getspnam_r(cnameptr: *const libc::c_char,
     let mut buffer = Buffer::new(buf, buflen);
     let conn = try_unwrap!(Connection::new("_nss_sectora_getspnam_r"), errnop);
     let msg = try_unwrap!(conn.communicate(CMsg::Sp(Sp::Nam(string_from(cnameptr)))), errnop);
-    if let DMsg::Sp { login, pass } = msg {
-        match unsafe { (*spptr).pack_args(&mut buffer, &login, &pass) } {
+    if let DMsg::Sp { login, pass, aging } = msg {
+        match unsafe { (*spptr).pack_args(&mut buffer, &login, &pass, &aging) } {
             Ok(_) => succeed!(),
+            Err(e) if malformed(&e) => fail!(errnop, Errno::ENOENT, NssStatus::NotFound),
             Err(_) => fail!(errnop, Errno::ERANGE, NssStatus::TryAgain),
         }
     }
@@ -207,12 +364,11 @@ pub unsafe extern "C" fn _nss_sectora_getspnam_r(cnameptr: *const libc::c_char,
 /// This function intended to be called from nss
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn _nss_sectora_setspent() -> libc::c_int {
-    let conn = try_unwrap!(Connection::new("_nss_sectora_setspent"));
-    let msg = try_unwrap!(conn.communicate(CMsg::Sp(Sp::Ent(Ent::Set(process::id())))));
+    let msg = try_unwrap!(set_ent(&SP_ENT, "_nss_sectora_setspent", |ent| CMsg::Sp(Sp::Ent(ent))));
     if let DMsg::Success = msg {
         return libc::c_int::from(NssStatus::Success);
     }
-    libc::c_int::from(NssStatus::Success)
+    libc::c_int::from(NssStatus::TryAgain)
 }
 
 /// # Safety
@@ -222,16 +378,26 @@ pub unsafe extern "C" fn _nss_sectora_setspent() -> libc::c_int {
 pub unsafe extern "C" fn _nss_sectora_getspent_r(spptr: *mut Spwd, buf: *mut libc::c_char, buflen: libc::size_t,
                                                  errnop: *mut libc::c_int)
                                                  -> libc::c_int {
-    let mut buffer = Buffer::new(buf, buflen);
-    let conn = try_unwrap!(Connection::new("_nss_sectora_getspent_r"), errnop);
-    let msg = try_unwrap!(conn.communicate(CMsg::Sp(Sp::Ent(Ent::Get(process::id())))), errnop);
-    if let DMsg::Sp { login, pass } = msg {
-        match unsafe { (*spptr).pack_args(&mut buffer, &login, &pass) } {
-            Ok(_) => succeed!(),
-            Err(_) => fail!(errnop, Errno::ERANGE, NssStatus::TryAgain),
+    // An entry that can never be returned is skipped, so that the enumeration goes on past it
+    loop {
+        let mut buffer = Buffer::new(buf, buflen);
+        let msg = try_unwrap!(get_ent(&SP_ENT, "_nss_sectora_getspent_r", |ent| CMsg::Sp(Sp::Ent(ent))),
+                              errnop);
+        if let DMsg::Sp { login, pass, aging } = &msg {
+            match unsafe { (*spptr).pack_args(&mut buffer, login, pass, aging) } {
+                Ok(_) => succeed!(),
+                Err(e) if malformed(&e) => {
+                    log::warn!("skipping an entry that cannot be returned: {:?}", e);
+                    continue;
+                }
+                Err(_) => {
+                    unget_ent(&SP_ENT, msg);
+                    fail!(errnop, Errno::ERANGE, NssStatus::TryAgain)
+                }
+            }
         }
+        fail!(errnop, Errno::ENOENT, NssStatus::NotFound)
     }
-    fail!(errnop, Errno::ENOENT, NssStatus::NotFound)
 }
 
 /// # Safety
@@ -239,8 +405,7 @@ pub unsafe extern "C" fn _nss_sectora_getspent_r(spptr: *mut Spwd, buf: *mut lib
 /// This function intended to be called from nss
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn _nss_sectora_endspent() -> libc::c_int {
-    let conn = try_unwrap!(Connection::new("_nss_sectora_endspent"));
-    let msg = try_unwrap!(conn.communicate(CMsg::Sp(Sp::Ent(Ent::End(process::id())))));
+    let msg = try_unwrap!(end_ent(&SP_ENT, |ent| CMsg::Sp(Sp::Ent(ent))));
     if let DMsg::Success = msg {
         return libc::c_int::from(NssStatus::Success);
     }
@@ -255,12 +420,12 @@ pub unsafe extern "C" fn _nss_sectora_getgrgid_r(gid: libc::gid_t, grptr: *mut G
                                                  buflen: libc::size_t, errnop: *mut libc::c_int)
                                                  -> libc::c_int {
     let mut buffer = Buffer::new(buf, buflen);
-    let conn = try_unwrap!(Connection::new("_nss_sectora_getgrgid_r"), errnop);
-    let msg = try_unwrap!(conn.communicate(CMsg::Gr(Gr::Gid(gid as u64))), errnop);
+    let msg = try_unwrap!(ask("_nss_sectora_getgrgid_r", CMsg::Gr(Gr::Gid(gid as u64))), errnop);
     if let DMsg::Gr { sector } = msg {
         let members: Vec<&str> = sector.members.values().map(|m| m.login.as_str()).collect();
         match unsafe { (*grptr).pack_args(&mut buffer, &sector.get_group(), u64::from(gid), &members) } {
             Ok(_) => succeed!(),
+            Err(e) if malformed(&e) => fail!(errnop, Errno::ENOENT, NssStatus::NotFound),
             Err(_) => fail!(errnop, Errno::ERANGE, NssStatus::TryAgain),
         }
     }
@@ -276,12 +441,13 @@ pub unsafe extern "C" fn _nss_sectora_getgrnam_r(cnameptr: *const libc::c_char,
                                                  errnop: *mut libc::c_int)
                                                  -> libc::c_int {
     let mut buffer = Buffer::new(buf, buflen);
-    let conn = try_unwrap!(Connection::new("_nss_sectora_getgrnam_r"), errnop);
-    let msg = try_unwrap!(conn.communicate(CMsg::Gr(Gr::Nam(string_from(cnameptr)))), errnop);
+    let msg = try_unwrap!(ask("_nss_sectora_getgrnam_r", CMsg::Gr(Gr::Nam(string_from(cnameptr)))),
+                          errnop);
     if let DMsg::Gr { sector } = msg {
         let members: Vec<&str> = sector.members.values().map(|m| m.login.as_str()).collect();
         match unsafe { (*grptr).pack_args(&mut buffer, &sector.get_group(), sector.get_gid(), &members) } {
             Ok(_) => succeed!(),
+            Err(e) if malformed(&e) => fail!(errnop, Errno::ENOENT, NssStatus::NotFound),
             Err(_) => fail!(errnop, Errno::ERANGE, NssStatus::TryAgain),
         }
     }
@@ -293,12 +459,11 @@ pub unsafe extern "C" fn _nss_sectora_getgrnam_r(cnameptr: *const libc::c_char,
 /// This function intended to be called from nss
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn _nss_sectora_setgrent() -> libc::c_int {
-    let conn = try_unwrap!(Connection::new("_nss_sectora_setgrent"));
-    let msg = try_unwrap!(conn.communicate(CMsg::Gr(Gr::Ent(Ent::Set(process::id())))));
+    let msg = try_unwrap!(set_ent(&GR_ENT, "_nss_sectora_setgrent", |ent| CMsg::Gr(Gr::Ent(ent))));
     if let DMsg::Success = msg {
         return libc::c_int::from(NssStatus::Success);
     }
-    libc::c_int::from(NssStatus::Success)
+    libc::c_int::from(NssStatus::TryAgain)
 }
 
 /// # Safety
@@ -308,17 +473,27 @@ pub unsafe extern "C" fn _nss_sectora_setgrent() -> libc::c_int {
 pub unsafe extern "C" fn _nss_sectora_getgrent_r(grptr: *mut Group, buf: *mut libc::c_char, buflen: libc::size_t,
                                                  errnop: *mut libc::c_int)
                                                  -> libc::c_int {
-    let mut buffer = Buffer::new(buf, buflen);
-    let conn = try_unwrap!(Connection::new("_nss_sectora_getgrent_r"), errnop);
-    let msg = try_unwrap!(conn.communicate(CMsg::Gr(Gr::Ent(Ent::Get(process::id())))), errnop);
-    if let DMsg::Gr { sector } = msg {
-        let members: Vec<&str> = sector.members.values().map(|m| m.login.as_str()).collect();
-        match unsafe { (*grptr).pack_args(&mut buffer, &sector.get_group(), sector.get_gid(), &members) } {
-            Ok(_) => succeed!(),
-            Err(_) => fail!(errnop, Errno::ERANGE, NssStatus::TryAgain),
+    // An entry that can never be returned is skipped, so that the enumeration goes on past it
+    loop {
+        let mut buffer = Buffer::new(buf, buflen);
+        let msg = try_unwrap!(get_ent(&GR_ENT, "_nss_sectora_getgrent_r", |ent| CMsg::Gr(Gr::Ent(ent))),
+                              errnop);
+        if let DMsg::Gr { sector } = &msg {
+            let members: Vec<&str> = sector.members.values().map(|m| m.login.as_str()).collect();
+            match unsafe { (*grptr).pack_args(&mut buffer, &sector.get_group(), sector.get_gid(), &members) } {
+                Ok(_) => succeed!(),
+                Err(e) if malformed(&e) => {
+                    log::warn!("skipping an entry that cannot be returned: {:?}", e);
+                    continue;
+                }
+                Err(_) => {
+                    unget_ent(&GR_ENT, msg);
+                    fail!(errnop, Errno::ERANGE, NssStatus::TryAgain)
+                }
+            }
         }
+        fail!(errnop, Errno::ENOENT, NssStatus::NotFound)
     }
-    fail!(errnop, Errno::ENOENT, NssStatus::NotFound)
 }
 
 /// # Safety
@@ -326,8 +501,7 @@ pub unsafe extern "C" fn _nss_sectora_getgrent_r(grptr: *mut Group, buf: *mut li
 /// This function intended to be called from nss
 #[unsafe(no_mangle)]
 pub unsafe extern "C" fn _nss_sectora_endgrent() -> libc::c_int {
-    let conn = try_unwrap!(Connection::new("_nss_sectora_endgrent"));
-    let msg = try_unwrap!(conn.communicate(CMsg::Gr(Gr::Ent(Ent::End(process::id())))));
+    let msg = try_unwrap!(end_ent(&GR_ENT, |ent| CMsg::Gr(Gr::Ent(ent))));
     if let DMsg::Success = msg {
         return libc::c_int::from(NssStatus::Success);
     }
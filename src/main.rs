@@ -10,6 +10,7 @@ use log::debug;
 use message::*;
 use std::env;
 use std::io::{Error, ErrorKind};
+use std::process;
 use structs::Config;
 
 #[derive(Debug, Parser)]
@@ -19,14 +20,29 @@ enum Command {
     Key { user: String },
     /// Executes pam check
     Pam,
+    /// Sets password hash of the caller (reads from stdin if omitted)
+    Passwd { hash: Option<String> },
+    /// Denies access of the user on this host (root only)
+    Deny { login: String },
+    /// Exports or imports a signed snapshot for hosts without access to GitHub (root only)
+    Snapshot {
+        #[clap(subcommand)]
+        action: SnapshotAction,
+    },
     /// Check configuration
     Check { confpath: std::path::PathBuf },
-    /// Cleans caches up
+    /// Resets the cached snapshot and fetches it again
     #[clap(alias = "cleanup")]
     CleanUp,
     /// Get rate limit for github api
     #[clap(alias = "ratelimit")]
     RateLimit,
+    /// Displays the health of the daemon; exits with 0 when healthy, 1 on warning, 2 when critical, 3 when unknown
+    Status {
+        /// Prints JSON instead of text
+        #[clap(long)]
+        json: bool,
+    },
     /// Displays version details
     Version,
     /// Displays completion
@@ -36,6 +52,15 @@ enum Command {
     },
 }
 
+#[derive(Debug, Parser)]
+#[clap(rename_all = "kebab-case")]
+enum SnapshotAction {
+    /// Writes the current snapshot signed with the configured signing key
+    Export { path: std::path::PathBuf },
+    /// Loads a snapshot after checking its signature with the configured verify key
+    Import { path: std::path::PathBuf },
+}
+
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug, Parser)]
 #[clap(rename_all = "kebab-case")]
@@ -47,6 +72,101 @@ enum Shell {
     Elvish,
 }
 
+/// Exit codes of `status`, as monitoring plugins use them
+#[derive(Debug, Clone, Copy)]
+enum Health {
+    Ok = 0,
+    /// The last refresh failed while the snapshot still grants access
+    Warning = 1,
+    /// No snapshot, or one too stale to grant access
+    Critical = 2,
+    /// The daemon did not answer
+    Unknown = 3,
+}
+
+impl Health {
+    fn name(self) -> &'static str {
+        match self {
+            Health::Ok => "ok",
+            Health::Warning => "warning",
+            Health::Critical => "critical",
+            Health::Unknown => "unknown",
+        }
+    }
+}
+
+fn show_unknown(json: bool, error: &str) -> Health {
+    if json {
+        println!("{}",
+                 serde_json::json!({ "health": Health::Unknown.name(), "error": error }));
+    } else {
+        println!("health: {}", Health::Unknown.name());
+        println!("error: {}", error);
+    }
+    Health::Unknown
+}
+
+fn show_status(conn: &connection::Connection, json: bool) -> Health {
+    let Ok(DaemonMessage::Status { uptime,
+                                   last_refresh,
+                                   refresh_error,
+                                   snapshot_age,
+                                   max_stale,
+                                   stale,
+                                   sectors,
+                                   members,
+                                   cache_dir,
+                                   config_path, }) = conn.communicate(ClientMessage::Status)
+    else {
+        return show_unknown(json, "no status from the daemon");
+    };
+    let health = if stale || snapshot_age.is_none() {
+        Health::Critical
+    } else if refresh_error.is_some() {
+        Health::Warning
+    } else {
+        Health::Ok
+    };
+    if json {
+        let status = serde_json::json!({ "health": health.name(),
+                                         "uptime": uptime,
+                                         "last_refresh": last_refresh,
+                                         "refresh_error": refresh_error,
+                                         "snapshot_age": snapshot_age,
+                                         "max_stale": max_stale,
+                                         "stale": stale,
+                                         "sectors": sectors,
+                                         "members": members,
+                                         "cache_dir": cache_dir,
+                                         "config_path": config_path });
+        println!("{}", status);
+        return health;
+    }
+    println!("health: {}", health.name());
+    println!("uptime: {}s", uptime);
+    match last_refresh {
+        None => println!("last refresh: none since the start"),
+        Some(at) => println!("last refresh: {}s ago",
+                             structs::epoch_secs(std::time::SystemTime::now()) - at),
+    }
+    if let Some(error) = refresh_error {
+        println!("refresh error: {}", error);
+    }
+    match snapshot_age {
+        None => println!("snapshot age: none"),
+        Some(age) => println!("snapshot age: {}s", age),
+    }
+    match max_stale {
+        None => println!("max stale: unlimited"),
+        Some(max) => println!("max stale: {}s", max),
+    }
+    println!("access: {}", if stale { "denied (stale)" } else { "granted" });
+    println!("sectors: {}, members: {}", sectors, members);
+    println!("cache dir: {}", cache_dir);
+    println!("config: {}", config_path);
+    health
+}
+
 fn show_keys(conn: &connection::Connection, user: &str) -> Result<(), Error> {
     match conn.communicate(ClientMessage::Key { user: user.to_owned() }) {
         Ok(DaemonMessage::Key { keys }) => {
@@ -57,11 +177,22 @@ fn show_keys(conn: &connection::Connection, user: &str) -> Result<(), Error> {
     }
 }
 
+fn absolute_path(path: &std::path::Path) -> Result<String, Error> {
+    std::path::absolute(path)?.into_os_string()
+                              .into_string()
+                              .map_err(|_| Error::new(ErrorKind::InvalidInput, "path is not valid UTF-8"))
+}
+
 fn main() -> Result<(), Error> {
     let command = Command::parse();
     let conn = match connection::Connection::new(&format!("{:?}", command)) {
         Ok(conn) => conn,
-        Err(err) => return Err(Error::other(format!("{:?}", err))),
+        Err(err) => match command {
+            Command::Status { json } => {
+                process::exit(show_unknown(json, &format!("daemon unreachable: {:?}", err)) as i32)
+            }
+            _ => return Err(Error::other(format!("{:?}", err))),
+        },
     };
     debug!("connected to socket: {:?}", conn);
 
@@ -84,9 +215,42 @@ fn main() -> Result<(), Error> {
             },
             Err(_) => return Err(Error::new(ErrorKind::ConnectionRefused, "failed")),
         },
+        Command::Passwd { hash } => {
+            let pass = match hash {
+                Some(hash) => hash,
+                None => {
+                    let mut line = String::default();
+                    std::io::stdin().read_line(&mut line)?;
+                    String::from(line.trim())
+                }
+            };
+            match conn.communicate(ClientMessage::Passwd { pass }) {
+                Ok(DaemonMessage::Success) => return Ok(()),
+                Ok(DaemonMessage::Error { message }) => return Err(Error::new(ErrorKind::PermissionDenied, message)),
+                _ => return Err(Error::other("failed")),
+            }
+        }
+        Command::Deny { login } => match conn.communicate(ClientMessage::Deny { user: login }) {
+            Ok(DaemonMessage::Success) => return Ok(()),
+            Ok(DaemonMessage::Error { message }) => return Err(Error::new(ErrorKind::PermissionDenied, message)),
+            _ => return Err(Error::other("failed")),
+        },
+        Command::Snapshot { action } => {
+            // The daemon resolves the path, so it must not depend on the working directory of this command
+            let msg = match action {
+                SnapshotAction::Export { path } => ClientMessage::SnapshotExport { path: absolute_path(&path)? },
+                SnapshotAction::Import { path } => ClientMessage::SnapshotImport { path: absolute_path(&path)? },
+            };
+            match conn.communicate(msg) {
+                Ok(DaemonMessage::Success) => return Ok(()),
+                Ok(DaemonMessage::Error { message }) => return Err(Error::other(message)),
+                _ => return Err(Error::other("failed")),
+            }
+        }
         Command::CleanUp => match conn.communicate(ClientMessage::CleanUp) {
-            Ok(_) => return Ok(()),
-            Err(_) => return Err(Error::other("failed")),
+            Ok(DaemonMessage::Success) => return Ok(()),
+            Ok(DaemonMessage::Error { message }) => return Err(Error::other(message)),
+            _ => return Err(Error::other("failed")),
         },
         Command::RateLimit => match conn.communicate(ClientMessage::RateLimit) {
             Ok(DaemonMessage::RateLimit { limit,
@@ -96,6 +260,7 @@ fn main() -> Result<(), Error> {
             }
             _ => return Err(Error::other("failed")),
         },
+        Command::Status { json } => process::exit(show_status(&conn, json) as i32),
         Command::Version => {
             println!("{}",
                      concat!(env!("CARGO_PKG_VERSION"),
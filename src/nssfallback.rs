@@ -0,0 +1,121 @@
+use crate::error::Error;
+use crate::fallback::{FALLBACK_PATH, FALLBACK_VERSION, Fallback, PwEntry};
+use crate::message::{ClientMessage, DaemonMessage, Gr, Pw};
+use crate::structs::SectorGroup;
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::io::Read;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+
+/// Upper bound of the file, which a process resolving a name reads whole while the daemon is down
+const MAX_FALLBACK_LEN: u64 = 16 * 1024 * 1024;
+
+/// Device, inode and modification time of the file; the daemon replaces it by a rename, which changes the inode
+type FileId = (u64, u64, i64, i64);
+
+/// Path and owner set by the integration tests of the library in place of `FALLBACK_PATH` and root
+static TEST_FALLBACK: OnceLock<(PathBuf, u32)> = OnceLock::new();
+/// Entries of the file last read by this process, parsed again only when another file is in place
+static LOADED: Mutex<Option<(FileId, Arc<Entries>)>> = Mutex::new(None);
+
+/// Entries of the fallback file indexed the way the NSS functions look them up; on duplicates the first entry wins,
+/// as in the directory of the daemon
+pub struct Entries {
+    passwd: Vec<PwEntry>,
+    group: Vec<SectorGroup>,
+    uids: HashMap<u64, usize>,
+    logins: HashMap<String, usize>,
+    gids: HashMap<u64, usize>,
+    groups: HashMap<String, usize>,
+}
+
+pub fn set_test_path(path: &Path) {
+    TEST_FALLBACK.get_or_init(|| (path.to_path_buf(), nix::unistd::geteuid().as_raw()));
+}
+
+pub fn path() -> &'static Path {
+    TEST_FALLBACK.get()
+                 .map_or(Path::new(FALLBACK_PATH), |(path, _)| path.as_path())
+}
+
+/// Entries of the fallback file unless anyone but root could have written it
+pub fn load() -> Result<Arc<Entries>, Error> {
+    let owner = TEST_FALLBACK.get().map_or(0, |(_, owner)| *owner);
+    let mut file = File::open(path())?;
+    let metadata = file.metadata()?;
+    if metadata.uid() != owner || metadata.mode() & 0o022 != 0 || metadata.len() > MAX_FALLBACK_LEN {
+        log::warn!("ignoring {:?}: owner {} mode {:o} size {}",
+                   path(),
+                   metadata.uid(),
+                   metadata.mode() & 0o777,
+                   metadata.len());
+        return Err(Error::Permission);
+    }
+    let id = (metadata.dev(), metadata.ino(), metadata.mtime(), metadata.mtime_nsec());
+    let mut loaded = LOADED.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some((loaded_id, entries)) = loaded.as_ref()
+       && *loaded_id == id
+    {
+        return Ok(Arc::clone(entries));
+    }
+    let mut contents = String::default();
+    file.read_to_string(&mut contents)?;
+    let fallback = serde_json::from_str::<Fallback>(&contents)?;
+    if fallback.version != FALLBACK_VERSION {
+        return Err(Error::Serde);
+    }
+    let entries = Arc::new(Entries::new(fallback));
+    *loaded = Some((id, Arc::clone(&entries)));
+    Ok(entries)
+}
+
+fn not_found() -> DaemonMessage { DaemonMessage::Error { message: String::from("not found") } }
+
+impl Entries {
+    fn new(fallback: Fallback) -> Self {
+        let mut entries = Entries { passwd: fallback.passwd,
+                                    group: fallback.group,
+                                    uids: HashMap::new(),
+                                    logins: HashMap::new(),
+                                    gids: HashMap::new(),
+                                    groups: HashMap::new() };
+        for (idx, entry) in entries.passwd.iter().enumerate() {
+            entries.uids.entry(entry.uid).or_insert(idx);
+            entries.logins.entry(entry.login.clone()).or_insert(idx);
+        }
+        for (idx, sector) in entries.group.iter().enumerate() {
+            entries.gids.entry(sector.get_gid()).or_insert(idx);
+            entries.groups.entry(sector.get_group()).or_insert(idx);
+        }
+        entries
+    }
+
+    /// What the daemon would answer to a passwd or group lookup, `None` for the requests only the daemon may answer
+    pub fn answer(&self, msg: &ClientMessage) -> Option<DaemonMessage> {
+        let pw = |idx: Option<&usize>| idx.map_or_else(not_found, |&idx| self.passwd[idx].clone().into());
+        let gr = |idx: Option<&usize>| {
+            idx.map_or_else(not_found, |&idx| DaemonMessage::Gr { sector: self.group[idx].clone() })
+        };
+        match msg {
+            ClientMessage::Pw(Pw::Uid(uid)) => Some(pw(self.uids.get(uid))),
+            ClientMessage::Pw(Pw::Nam(name)) => Some(pw(self.logins.get(name))),
+            ClientMessage::Gr(Gr::Gid(gid)) => Some(gr(self.gids.get(gid))),
+            ClientMessage::Gr(Gr::Nam(name)) => Some(gr(self.groups.get(name))),
+            _ => None,
+        }
+    }
+
+    /// Entries of a passwd or group enumeration, `None` for the other databases
+    pub fn entries(&self, msg: &ClientMessage) -> Option<VecDeque<DaemonMessage>> {
+        match msg {
+            ClientMessage::Pw(_) => Some(self.passwd.iter().cloned().map(DaemonMessage::from).collect()),
+            ClientMessage::Gr(_) => Some(self.group
+                                             .iter()
+                                             .map(|sector| DaemonMessage::Gr { sector: sector.clone() })
+                                             .collect()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use crate::structs::{BreakGlassUser, Config, Member, RepoConfig, Sector, SectorGroup, SectorType, TeamConfig};
+
+/// How the daemon applies the per-sector and per-user settings; kept out of `structs` as no client needs them
+impl Config {
+    fn team_conf(&self, name: &str) -> Option<&TeamConfig> { self.team.iter().find(|t| t.name == name) }
+
+    fn repo_conf(&self, name: &str) -> Option<&RepoConfig> { self.repo.iter().find(|r| r.name == name) }
+
+    pub fn max_days(&self, sector: &Sector) -> Option<i64> {
+        match sector.sector_type {
+            SectorType::Team => self.team_conf(&sector.name).and_then(|t| t.max_days),
+            SectorType::Repo => self.repo_conf(&sector.name).and_then(|r| r.max_days),
+            SectorType::Local => None,
+        }
+    }
+
+    pub fn expire(&self, sector: &Sector) -> Option<i64> {
+        match sector.sector_type {
+            SectorType::Team | SectorType::Local => None,
+            SectorType::Repo => self.repo_conf(&sector.name)
+                                    .and_then(|r| r.expire.as_ref())
+                                    .and_then(|dt| dt.date.as_ref())
+                                    .map(|d| days_from_civil(i64::from(d.year), i64::from(d.month), i64::from(d.day))),
+        }
+    }
+
+    /// `not_before` and `not_after` of the sector in seconds since the epoch
+    pub fn sector_window(&self, sector: &Sector) -> Window {
+        match sector.sector_type {
+            SectorType::Team => self.team_conf(&sector.name)
+                                    .map(|t| Window::new(&t.not_before, &t.not_after))
+                                    .unwrap_or_default(),
+            SectorType::Repo => self.repo_conf(&sector.name)
+                                    .map(|r| Window::new(&r.not_before, &r.not_after))
+                                    .unwrap_or_default(),
+            SectorType::Local => Window::default(),
+        }
+    }
+
+    /// True when the sector has no `hosts`/`tags` rules or one of them matches this host
+    pub fn applies_to_host(&self, sector: &Sector, hostname: &str) -> bool {
+        let scope = match sector.sector_type {
+            SectorType::Team => self.team_conf(&sector.name).map(|t| (&t.hosts, &t.tags)),
+            SectorType::Repo => self.repo_conf(&sector.name).map(|r| (&r.hosts, &r.tags)),
+            SectorType::Local => None,
+        };
+        let Some((hosts, tags)) = scope else { return true };
+        if hosts.is_empty() && tags.is_empty() {
+            return true;
+        }
+        hosts.iter()
+             .filter_map(|h| match glob::Pattern::new(h) {
+                 Ok(pattern) => Some(pattern),
+                 Err(e) => {
+                     log::warn!("invalid hosts pattern {:?} in sector {}: {}", h, sector.name, e);
+                     None
+                 }
+             })
+             .any(|p| p.matches(hostname))
+        || tags.iter().any(|t| self.host_tags.contains(t))
+    }
+
+    pub fn break_glass_sector(&self) -> Option<SectorGroup> {
+        let bg = self.break_glass.as_ref()?;
+        let members = bg.user
+                        .iter()
+                        .map(|u| {
+                            (u.login.clone(),
+                             Member { id: u.uid,
+                                      login: u.login.clone() })
+                        })
+                        .collect();
+        Some(SectorGroup { sector: Sector { id: bg.gid,
+                                            name: bg.group.clone(),
+                                            sector_type: SectorType::Local },
+                           gid: Some(bg.gid),
+                           group: Some(bg.group.clone()),
+                           members })
+    }
+
+    pub fn break_glass_user(&self, login: &str) -> Option<&BreakGlassUser> {
+        self.break_glass.as_ref()?.user.iter().find(|u| u.login == login)
+    }
+
+    /// `not_before` and `not_after` of the user's `[[grant]]` entry in seconds since the epoch
+    pub fn grant_window(&self, login: &str) -> Window {
+        self.grant
+            .iter()
+            .find(|g| g.login == login)
+            .map(|g| Window::new(&g.not_before, &g.not_after))
+            .unwrap_or_default()
+    }
+
+    /// Earliest `not_before` or `not_after` of sectors and grants after `now`, when access may change next
+    pub fn next_window_change(&self, now: i64) -> Option<i64> {
+        self.team
+            .iter()
+            .map(|t| Window::new(&t.not_before, &t.not_after))
+            .chain(self.repo.iter().map(|r| Window::new(&r.not_before, &r.not_after)))
+            .chain(self.grant.iter().map(|g| Window::new(&g.not_before, &g.not_after)))
+            .flat_map(|w| [w.not_before, w.not_after])
+            .flatten()
+            .filter(|&t| t > now)
+            .min()
+    }
+}
+
+/// Validity period of a sector or a grant
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Window {
+    pub not_before: Option<i64>,
+    pub not_after: Option<i64>,
+}
+
+impl Window {
+    fn new(not_before: &Option<toml::value::Datetime>, not_after: &Option<toml::value::Datetime>) -> Self {
+        Self { not_before: not_before.as_ref().and_then(datetime_to_epoch),
+               not_after: not_after.as_ref().and_then(datetime_to_epoch) }
+    }
+
+    pub fn contains(&self, now: i64) -> bool {
+        self.not_before.is_none_or(|t| t <= now) && self.not_after.is_none_or(|t| now < t)
+    }
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date
+pub fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = (if y >= 0 { y } else { y - 399 }) / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Seconds since the epoch; a date without time means midnight and a missing offset means UTC
+fn datetime_to_epoch(dt: &toml::value::Datetime) -> Option<i64> {
+    let date = dt.date.as_ref()?;
+    let days = days_from_civil(i64::from(date.year), i64::from(date.month), i64::from(date.day));
+    let secs = dt.time
+                 .as_ref()
+                 .map(|t| i64::from(t.hour) * 3600 + i64::from(t.minute) * 60 + i64::from(t.second.unwrap_or(0)))
+                 .unwrap_or(0);
+    let offset = match dt.offset {
+        Some(toml::value::Offset::Custom { minutes }) => i64::from(minutes) * 60,
+        _ => 0,
+    };
+    Some(days * 86400 + secs - offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml: &str) -> Config {
+        toml::from_str(&format!("token = \"dummy\"\norg = \"soundtribe\"\n{}", toml)).unwrap()
+    }
+
+    fn team(name: &str) -> Sector {
+        Sector { id: 9,
+                 name: String::from(name),
+                 sector_type: SectorType::Team }
+    }
+
+    #[test]
+    fn date_only_values_are_midnight_utc() {
+        let window = config("[[team]]\nname = \"sector9\"\nnot_before = 2024-03-01\n").sector_window(&team("sector9"));
+        assert_eq!(window.not_before, Some(1_709_251_200));
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+    }
+
+    #[test]
+    fn offsets_are_applied() {
+        let conf = config("[[grant]]\nlogin = \"alice\"\nnot_before = 2024-03-01T09:00:00+09:00\n\
+                           not_after = 2024-03-01T00:00:00Z\n");
+        let window = conf.grant_window("alice");
+        assert_eq!(window.not_before, Some(1_709_251_200));
+        assert_eq!(window.not_after, Some(1_709_251_200));
+    }
+
+    #[test]
+    fn not_after_is_exclusive() {
+        let window = Window { not_before: Some(100),
+                              not_after: Some(200) };
+        assert!(!window.contains(99));
+        assert!(window.contains(100));
+        assert!(window.contains(199));
+        assert!(!window.contains(200));
+        assert!(Window::default().contains(0));
+    }
+
+    #[test]
+    fn sectors_without_rules_apply_to_every_host() {
+        let conf = config("[[team]]\nname = \"sector9\"\n");
+        assert!(conf.applies_to_host(&team("sector9"), "web01"));
+        assert!(conf.applies_to_host(&team("unconfigured"), "web01"));
+    }
+
+    #[test]
+    fn sectors_apply_by_hostname_glob() {
+        let conf = config("[[team]]\nname = \"sector9\"\nhosts = [\"web*\", \"db[0-9]\"]\n");
+        assert!(conf.applies_to_host(&team("sector9"), "web01"));
+        assert!(conf.applies_to_host(&team("sector9"), "db1"));
+        assert!(!conf.applies_to_host(&team("sector9"), "db10"));
+        assert!(!conf.applies_to_host(&team("sector9"), "mail"));
+    }
+
+    #[test]
+    fn sectors_apply_by_host_tag() {
+        let conf = config("host_tags = [\"prod\"]\n[[team]]\nname = \"sector9\"\ntags = [\"prod\"]\n\
+                           [[team]]\nname = \"staging\"\ntags = [\"staging\"]\n");
+        assert!(conf.applies_to_host(&team("sector9"), "web01"));
+        assert!(!conf.applies_to_host(&team("staging"), "web01"));
+    }
+}
@@ -0,0 +1,60 @@
+use crate::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::Path;
+
+/// Fails unless the file is owned by the daemon and has none of the `forbidden` mode bits
+pub fn check_owner(path: &Path, forbidden: u32) -> Result<(), Error> {
+    let metadata = fs::metadata(path)?;
+    let euid = nix::unistd::geteuid().as_raw();
+    if metadata.uid() != euid || metadata.mode() & forbidden != 0 {
+        log::warn!("ignoring {:?}: owner {} mode {:o}, expected owner {} without mode {:o}",
+                   path,
+                   metadata.uid(),
+                   metadata.mode() & 0o777,
+                   euid,
+                   forbidden);
+        return Err(Error::Permission);
+    }
+    Ok(())
+}
+
+/// Creates the directory with `mode` if missing and fails unless it is owned by the daemon and not writable by others
+pub fn ensure_dir(path: &Path, mode: u32) -> Result<(), Error> {
+    fs::DirBuilder::new().recursive(true).mode(mode).create(path)?;
+    check_owner(path, 0o022)
+}
+
+/// Fails unless processes of other users may reach the files in the directory
+pub fn check_searchable(path: &Path) -> Result<(), Error> {
+    if fs::metadata(path)?.mode() & 0o001 == 0 {
+        return Err(Error::Permission);
+    }
+    Ok(())
+}
+
+/// Reads a file only readable by the daemon
+pub fn read_private(path: &Path) -> Result<String, Error> {
+    check_owner(path, 0o077)?;
+    let mut contents = String::default();
+    File::open(path)?.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/// Replaces the file via a synced temporary file so readers never see a partial write
+pub fn write_atomic(path: &Path, contents: &[u8], mode: u32) -> Result<(), Error> {
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    fs::DirBuilder::new().recursive(true).mode(0o755).create(dir)?;
+    let tmp = path.with_extension("tmp");
+    let mut f = OpenOptions::new().write(true)
+                                  .create(true)
+                                  .truncate(true)
+                                  .mode(mode)
+                                  .open(&tmp)?;
+    f.write_all(contents)?;
+    f.sync_all()?;
+    fs::rename(&tmp, path)?;
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use crate::error::Error;
+use crate::securefile;
+use crate::structs::days_since_epoch;
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone)]
+pub struct ShadowEntry {
+    pub pass: String,
+    /// Day of the last password change, counted from 1970-01-01
+    pub lstchg: Option<i64>,
+}
+
+/// Password hashes of sector members, kept in a root-owned file (`login:hash:lstchg` per line).
+pub struct ShadowStore {
+    path: PathBuf,
+}
+
+impl ShadowStore {
+    pub fn new(path: &str) -> Self { Self { path: PathBuf::from(path) } }
+
+    fn load(&self) -> Result<BTreeMap<String, ShadowEntry>, Error> {
+        if !self.path.exists() {
+            return Ok(BTreeMap::new());
+        }
+        let contents = securefile::read_private(&self.path)?;
+        Ok(contents.lines()
+                   .filter_map(|l| l.split_once(':'))
+                   .map(|(login, rest)| {
+                       let (pass, lstchg) = match rest.split_once(':') {
+                           Some((pass, lstchg)) => (pass, lstchg.parse().ok().filter(|d: &i64| *d >= 0)),
+                           None => (rest, None),
+                       };
+                       (String::from(login),
+                        ShadowEntry { pass: String::from(pass),
+                                      lstchg })
+                   })
+                   .collect())
+    }
+
+    fn store(&self, entries: &BTreeMap<String, ShadowEntry>) -> Result<(), Error> {
+        // The parent may be shared, as with `/etc/shadow.sectora`, so it is only created, never chmod'ed
+        let dir = self.path.parent().unwrap_or(Path::new("/"));
+        fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+        securefile::check_owner(dir, 0o022)?;
+        let contents =
+            entries.iter()
+                   .map(|(login, entry)| format!("{}:{}:{}\n", login, entry.pass, entry.lstchg.unwrap_or(-1)))
+                   .collect::<String>();
+        securefile::write_atomic(&self.path, contents.as_bytes(), 0o600)
+    }
+
+    pub fn get(&self, login: &str) -> Option<ShadowEntry> {
+        match self.load() {
+            Ok(mut entries) => entries.remove(login),
+            Err(e) => {
+                log::warn!("failed to load shadow store: {:?}", e);
+                None
+            }
+        }
+    }
+
+    pub fn set(&self, login: &str, pass: &str) -> Result<(), Error> {
+        if !is_valid_hash(pass) {
+            return Err(Error::InvalidPass);
+        }
+        let mut entries = self.load()?;
+        entries.insert(String::from(login),
+                       ShadowEntry { pass: String::from(pass),
+                                     lstchg: Some(days_since_epoch(SystemTime::now())) });
+        self.store(&entries)
+    }
+}
+
+/// Accepts crypt(3) style hashes such as the output of `mkpasswd -m sha-512`.
+fn is_valid_hash(pass: &str) -> bool {
+    pass.starts_with('$') && !pass.contains(|c: char| c == ':' || c.is_whitespace() || c.is_control())
+}
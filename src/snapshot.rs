@@ -0,0 +1,241 @@
+use crate::error::Error;
+use crate::securefile;
+use crate::structs::{Config, SectorGroup};
+use openssl::pkey::{Id, PKey};
+use openssl::sign::{Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Bumped whenever the layout of `Snapshot` changes; older files are discarded
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// What the snapshot was built from; a snapshot from another source is refreshed before use
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Source {
+    pub endpoint: String,
+    pub org: String,
+    pub teams: Vec<String>,
+    pub repos: Vec<String>,
+}
+
+impl Source {
+    pub fn new(conf: &Config) -> Self {
+        Self { endpoint: conf.endpoint.clone(),
+               org: conf.org.clone(),
+               teams: conf.team.iter().map(|t| t.name.clone()).collect(),
+               repos: conf.repo.iter().map(|r| r.name.clone()).collect() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserKeys {
+    /// Seconds since the epoch when the keys were fetched
+    pub fetched_at: i64,
+    pub keys: Vec<String>,
+}
+
+/// Fully resolved sectors and public keys, stored as one file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    pub version: u32,
+    /// Seconds since the epoch when the sectors were fetched
+    pub built_at: i64,
+    pub source: Source,
+    pub sectors: Vec<SectorGroup>,
+    pub keys: HashMap<String, UserKeys>,
+}
+
+impl Snapshot {
+    pub fn new(source: Source, sectors: Vec<SectorGroup>, built_at: i64) -> Self {
+        Self { version: SNAPSHOT_VERSION,
+               built_at,
+               source,
+               sectors,
+               keys: HashMap::new() }
+    }
+
+    pub fn path(conf: &Config) -> PathBuf { Path::new(&conf.cache_dir).join("snapshot.json") }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let snapshot = serde_json::from_str::<Snapshot>(&securefile::read_private(path)?)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            log::warn!("discarding snapshot {:?} of version {}", path, snapshot.version);
+            return Err(Error::Serde);
+        }
+        Ok(snapshot)
+    }
+
+    pub fn store(&self, path: &Path) -> Result<(), Error> {
+        securefile::write_atomic(path, serde_json::to_string(self)?.as_bytes(), 0o600)
+    }
+
+    /// Refuses an imported snapshot built more than `max_age` seconds before `now`, built from another source, or not
+    /// newer than the `loaded` one
+    pub fn check_import(&self, source: &Source, max_age: u64, now: i64, loaded: Option<&Snapshot>)
+                        -> Result<(), Error> {
+        let age = now - self.built_at;
+        if age > max_age as i64 {
+            log::warn!("refusing snapshot built {} seconds ago", age);
+            return Err(Error::StaleSnapshot);
+        }
+        if self.source != *source {
+            log::warn!("refusing snapshot of another source: {:?}", self.source);
+            return Err(Error::StaleSnapshot);
+        }
+        if loaded.is_some_and(|loaded| loaded.built_at >= self.built_at) {
+            log::warn!("refusing snapshot not newer than the loaded one");
+            return Err(Error::StaleSnapshot);
+        }
+        Ok(())
+    }
+
+    pub fn remove(path: &Path) -> Result<(), Error> {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::from(e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A snapshot as exported to hosts without access to GitHub, signed with Ed25519
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SignedSnapshot {
+    /// The snapshot serialized as JSON, signed as is
+    pub payload: String,
+    /// Hex encoded signature of `payload`; empty when unsigned
+    #[serde(default)]
+    pub signature: String,
+}
+
+impl SignedSnapshot {
+    pub fn sign(snapshot: &Snapshot, signing_key: &Path) -> Result<Self, Error> {
+        let key = ed25519(PKey::private_key_from_pem(&securefile::read_private(signing_key)?.into_bytes())?)?;
+        let payload = serde_json::to_string(snapshot)?;
+        let signature = Signer::new_without_digest(&key)?.sign_oneshot_to_vec(payload.as_bytes())?;
+        Ok(Self { payload,
+                  signature: to_hex(&signature) })
+    }
+
+    /// The snapshot, if the signature verifies with the public key at `verify_key`
+    pub fn verify(&self, verify_key: &Path) -> Result<Snapshot, Error> {
+        let key = ed25519(PKey::public_key_from_pem(&std::fs::read(verify_key)?)?)?;
+        let signature = from_hex(&self.signature).ok_or(Error::Signature)?;
+        if !Verifier::new_without_digest(&key)?.verify_oneshot(&signature, self.payload.as_bytes())? {
+            return Err(Error::Signature);
+        }
+        let snapshot = serde_json::from_str::<Snapshot>(&self.payload)?;
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(Error::Serde);
+        }
+        Ok(snapshot)
+    }
+}
+
+/// Exported snapshots are signed with Ed25519 only
+fn ed25519<T>(key: PKey<T>) -> Result<PKey<T>, Error> {
+    if key.id() == Id::ED25519 {
+        Ok(key)
+    } else {
+        Err(Error::Signature)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, b| {
+                    let _ = write!(hex, "{:02x}", b);
+                    hex
+                })
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len()).step_by(2)
+                  .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                  .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::nid::Nid;
+    use openssl::pkey::Private;
+
+    fn source() -> Source {
+        Source { endpoint: String::from("https://api.github.com"),
+                 org: String::from("soundtribe"),
+                 teams: vec![String::from("sector9")],
+                 repos: Vec::new() }
+    }
+
+    fn snapshot(built_at: i64) -> Snapshot { Snapshot::new(source(), Vec::new(), built_at) }
+
+    /// Writes the private key as the daemon expects it and the public key next to it, returning both paths
+    fn key_files(name: &str, key: &PKey<Private>) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("sectora-snapshot-{}-{}", name, std::process::id()));
+        let (private, public) = (dir.join("signing.pem"), dir.join("verify.pem"));
+        securefile::write_atomic(&private, &key.private_key_to_pem_pkcs8().unwrap(), 0o600).unwrap();
+        securefile::write_atomic(&public, &key.public_key_to_pem().unwrap(), 0o644).unwrap();
+        (private, public)
+    }
+
+    fn ed25519_files(name: &str) -> (PathBuf, PathBuf) { key_files(name, &PKey::generate_ed25519().unwrap()) }
+
+    #[test]
+    fn signed_snapshot_verifies_with_the_public_key() {
+        let (private, public) = ed25519_files("round-trip");
+        let signed = SignedSnapshot::sign(&snapshot(100), &private).unwrap();
+        let verified = signed.verify(&public).unwrap();
+        assert_eq!(verified.built_at, 100);
+        assert_eq!(verified.source, source());
+    }
+
+    #[test]
+    fn tampered_or_unsigned_snapshots_are_refused() {
+        let (private, public) = ed25519_files("tampered");
+        let mut signed = SignedSnapshot::sign(&snapshot(100), &private).unwrap();
+        signed.payload = signed.payload.replace("\"built_at\":100", "\"built_at\":200");
+        assert!(matches!(signed.verify(&public), Err(Error::Signature)));
+        for signature in ["", "abc", "zz"] {
+            let unsigned = SignedSnapshot { payload: serde_json::to_string(&snapshot(100)).unwrap(),
+                                            signature: String::from(signature) };
+            assert!(matches!(unsigned.verify(&public), Err(Error::Signature)));
+        }
+        let (_, other) = ed25519_files("other");
+        let signed = SignedSnapshot::sign(&snapshot(100), &private).unwrap();
+        assert!(matches!(signed.verify(&other), Err(Error::Signature)));
+    }
+
+    #[test]
+    fn keys_other_than_ed25519_are_refused() {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let ec = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let (ec_private, ec_public) = key_files("ec", &ec);
+        assert!(matches!(SignedSnapshot::sign(&snapshot(100), &ec_private), Err(Error::Signature)));
+        let (private, _) = ed25519_files("ec-verify");
+        let signed = SignedSnapshot::sign(&snapshot(100), &private).unwrap();
+        assert!(matches!(signed.verify(&ec_public), Err(Error::Signature)));
+    }
+
+    #[test]
+    fn imports_must_be_recent_of_the_same_source_and_newer() {
+        let now = 10_000;
+        assert!(snapshot(now - 100).check_import(&source(), 100, now, None).is_ok());
+        assert!(matches!(snapshot(now - 101).check_import(&source(), 100, now, None),
+                         Err(Error::StaleSnapshot)));
+        let mut other = source();
+        other.org = String::from("other");
+        assert!(matches!(snapshot(now).check_import(&other, 100, now, None),
+                         Err(Error::StaleSnapshot)));
+        let loaded = snapshot(now - 10);
+        assert!(snapshot(now).check_import(&source(), 100, now, Some(&loaded)).is_ok());
+        assert!(matches!(snapshot(now - 10).check_import(&source(), 100, now, Some(&loaded)),
+                         Err(Error::StaleSnapshot)));
+        assert!(matches!(snapshot(now - 20).check_import(&source(), 100, now, Some(&loaded)),
+                         Err(Error::StaleSnapshot)));
+    }
+}
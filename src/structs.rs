@@ -6,7 +6,9 @@ use std::fs::File;
 use std::io::Read;
 use std::str::FromStr;
 use std::string::ToString;
+use std::sync::OnceLock;
 
+#[allow(dead_code)] // fields are read by sectorad only
 #[derive(Deserialize, Debug, Clone)]
 pub struct Config {
     pub token: String,
@@ -15,6 +17,8 @@ pub struct Config {
     pub team: Vec<TeamConfig>,
     #[serde(default = "default_repo")]
     pub repo: Vec<RepoConfig>,
+    #[serde(default = "default_grant")]
+    pub grant: Vec<GrantConfig>,
     #[serde(default = "default_endpoint")]
     pub endpoint: String,
     #[serde(default = "default_home")]
@@ -23,40 +27,79 @@ pub struct Config {
     pub sh: String,
     #[serde(default = "default_cache_duration")]
     pub cache_duration: u64,
+    /// Seconds before the snapshot reaches `cache_duration` at which the background refresh starts
+    #[serde(default = "default_refresh_ahead")]
+    pub refresh_ahead: u64,
+    /// Upper bound in seconds of the random offset spreading refreshes of many hosts
+    #[serde(default = "default_refresh_jitter")]
+    pub refresh_jitter: u64,
+    /// Number of users whose public keys are fetched at the same time during a refresh
+    #[serde(default = "default_key_fetch_concurrency")]
+    pub key_fetch_concurrency: usize,
+    /// Seconds after which a snapshot that failed to refresh stops granting access for PAM and keys
+    pub max_stale: Option<u64>,
+    /// Seconds a key lookup of a name without access is answered from memory
+    #[serde(default = "default_negative_cache_duration")]
+    pub negative_cache_duration: u64,
     #[serde(default = "default_cert_path")]
     pub cert_path: String,
     #[serde(default = "default_user_conf_path")]
     pub user_conf_path: String,
     #[serde(default = "default_cache_dir")]
     pub cache_dir: String,
+    /// Directory of the daemon socket, owned by the daemon and not writable by others
+    #[serde(default = "default_runtime_dir")]
+    pub runtime_dir: String,
+    #[serde(default = "default_shadow_path")]
+    pub shadow_path: String,
+    #[serde(default = "default_expiry_warning")]
+    pub expiry_warning: u64,
+    #[serde(default)]
+    pub deny_users: Vec<String>,
+    /// Only these users get access when not empty
+    #[serde(default)]
+    pub allow_only_users: Vec<String>,
+    #[serde(default = "default_override_path")]
+    pub override_path: String,
+    /// Logins refused in addition to the built-in reserved names and the local accounts
+    #[serde(default)]
+    pub reserved_users: Vec<String>,
+    /// Tags of this host matched against `tags` of sectors
+    #[serde(default)]
+    pub host_tags: Vec<String>,
+    pub break_glass: Option<BreakGlassConfig>,
+    /// Keys for exporting and importing signed snapshots
+    #[serde(default)]
+    pub snapshot: SnapshotConfig,
     pub proxy_url: Option<String>,
 }
 
 fn default_team() -> Vec<TeamConfig> { Vec::new() }
 fn default_repo() -> Vec<RepoConfig> { Vec::new() }
+fn default_grant() -> Vec<GrantConfig> { Vec::new() }
 fn default_endpoint() -> String { String::from("https://api.github.com") }
 fn default_home() -> String { String::from("/home/{}") }
 fn default_sh() -> String { String::from("/bin/bash") }
 fn default_cache_duration() -> u64 { 3600 }
+fn default_refresh_ahead() -> u64 { 300 }
+fn default_refresh_jitter() -> u64 { 60 }
+fn default_key_fetch_concurrency() -> usize { 8 }
+fn default_negative_cache_duration() -> u64 { 60 }
 fn default_cert_path() -> String { String::from("/etc/ssl/certs/ca-certificates.crt") }
 fn default_user_conf_path() -> String { String::from(".config/sectora.toml") }
-fn default_cache_dir() -> String {
-    let mut path = std::env::temp_dir();
-    path.push("sectora/cache");
-    String::from(path.as_os_str().to_str().unwrap_or_default())
-}
+fn default_cache_dir() -> String { String::from("/var/cache/sectora") }
 
-fn get_socket_path() -> String {
-    let mut path = std::env::temp_dir();
-    path.push("sectorad");
-    String::from(path.as_os_str().to_str().unwrap_or_default())
-}
+fn default_shadow_path() -> String { String::from("/var/lib/sectora/shadow") }
+fn default_expiry_warning() -> u64 { 86400 }
+fn default_override_path() -> String { String::from("/var/lib/sectora/override.toml") }
 
-fn get_socket_dir() -> String {
-    let mut path = std::env::temp_dir();
-    path.push("sectora");
-    String::from(path.as_os_str().to_str().unwrap_or_default())
-}
+/// Clients cannot read the config and run inside setuid programs, so they only know the directory fixed at build time
+pub const RUNTIME_DIR: &str = match option_env!("SECTORA_RUNTIME_DIR") {
+    Some(dir) => dir,
+    None => "/run/sectora",
+};
+
+fn default_runtime_dir() -> String { String::from(RUNTIME_DIR) }
 
 impl Config {
     #[allow(dead_code)]
@@ -68,23 +111,41 @@ impl Config {
     }
 }
 
+#[allow(dead_code)]
+pub fn epoch_secs(time: std::time::SystemTime) -> i64 {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[allow(dead_code)]
+pub fn days_since_epoch(time: std::time::SystemTime) -> i64 { epoch_secs(time) / 86400 }
+
 #[derive(Debug, Clone)]
 pub struct SocketConfig {
     pub socket_path: String,
-    pub socket_dir: String,
 }
 
+/// Runtime directory set by the integration tests of the library in place of `RUNTIME_DIR`
+#[allow(dead_code)]
+static TEST_RUNTIME_DIR: OnceLock<String> = OnceLock::new();
+
 impl SocketConfig {
-    pub fn new() -> Self {
-        SocketConfig { socket_path: get_socket_path(),
-                       socket_dir: get_socket_dir() }
+    #[allow(dead_code)]
+    pub fn new() -> Self { Self::from_runtime_dir(TEST_RUNTIME_DIR.get().map_or(RUNTIME_DIR, String::as_str)) }
+
+    #[allow(dead_code)]
+    pub fn set_test_runtime_dir(runtime_dir: &str) { TEST_RUNTIME_DIR.get_or_init(|| String::from(runtime_dir)); }
+
+    pub fn from_runtime_dir(runtime_dir: &str) -> Self {
+        let runtime_dir = runtime_dir.trim_end_matches('/');
+        SocketConfig { socket_path: format!("{}/sectorad.sock", runtime_dir) }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UserConfig {
     pub sh: Option<String>,
-    pub pass: Option<String>,
 }
 
 impl UserConfig {
@@ -108,6 +169,16 @@ pub struct TeamConfig {
     pub name: String,
     pub gid: Option<u64>,
     pub group: Option<String>,
+    /// Maximum password age in days
+    pub max_days: Option<i64>,
+    pub not_before: Option<toml::value::Datetime>,
+    pub not_after: Option<toml::value::Datetime>,
+    /// Hostname globs of the hosts this sector applies to
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Host tags (see `host_tags`) this sector applies to
+    #[serde(default)]
+    pub tags: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -121,12 +192,72 @@ pub struct RepoConfig {
     pub name: String,
     pub gid: Option<u64>,
     pub group: Option<String>,
+    /// Maximum password age in days
+    pub max_days: Option<i64>,
+    /// Date on which the accounts of the collaborators expire
+    pub expire: Option<toml::value::Datetime>,
+    pub not_before: Option<toml::value::Datetime>,
+    pub not_after: Option<toml::value::Datetime>,
+    /// Hostname globs of the hosts this sector applies to
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Host tags (see `host_tags`) this sector applies to
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// Ed25519 keys in PEM format signing exported snapshots and verifying imported ones
+#[allow(dead_code)] // fields are read by sectorad only
+#[derive(Deserialize, Debug, Clone)]
+pub struct SnapshotConfig {
+    pub signing_key: Option<String>,
+    pub verify_key: Option<String>,
+    /// Seconds after which an exported snapshot is refused on import
+    #[serde(default = "default_snapshot_max_age")]
+    pub max_age: u64,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self { signing_key: None,
+               verify_key: None,
+               max_age: default_snapshot_max_age() }
+    }
+}
+
+fn default_snapshot_max_age() -> u64 { 7 * 86400 }
+
+/// Emergency accounts served whatever the state of GitHub and the cache
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BreakGlassConfig {
+    pub group: String,
+    pub gid: u64,
+    #[serde(default)]
+    pub user: Vec<BreakGlassUser>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BreakGlassUser {
+    pub login: String,
+    pub uid: u64,
+    #[serde(default)]
+    pub keys: Vec<String>,
+}
+
+/// Time-bound access of a single user
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GrantConfig {
+    pub login: String,
+    pub not_before: Option<toml::value::Datetime>,
+    pub not_after: Option<toml::value::Datetime>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum SectorType {
     Team,
     Repo,
+    /// Break-glass group defined in the config
+    Local,
 }
 
 impl fmt::Display for SectorType {
@@ -134,6 +265,7 @@ impl fmt::Display for SectorType {
         match self {
             SectorType::Team => write!(f, "T"),
             SectorType::Repo => write!(f, "R"),
+            SectorType::Local => write!(f, "L"),
         }
     }
 }
@@ -144,6 +276,7 @@ impl FromStr for SectorType {
         match s {
             "T" => Ok(SectorType::Team),
             "R" => Ok(SectorType::Repo),
+            "L" => Ok(SectorType::Local),
             _ => Err(ParseSectorTypeError::UnknownType),
         }
     }
@@ -190,7 +323,7 @@ impl FromStr for Sector {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SectorGroup {
     pub sector: Sector,
     pub gid: Option<u64>,
@@ -285,20 +418,24 @@ impl FromStr for MemberGid {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct PublicKey {
-    pub id: u64,
-    pub key: String,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Rate {
-    pub limit: usize,
-    pub remaining: usize,
-    pub reset: usize,
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct RateLimit {
-    pub rate: Rate,
+/// Password aging fields of a shadow entry (`-1` means unset)
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShadowAging {
+    pub lstchg: i64,
+    pub min: i64,
+    pub max: i64,
+    pub warn: i64,
+    pub inact: i64,
+    pub expire: i64,
+}
+
+impl Default for ShadowAging {
+    fn default() -> Self {
+        Self { lstchg: -1,
+               min: -1,
+               max: -1,
+               warn: -1,
+               inact: -1,
+               expire: -1 }
+    }
 }
@@ -0,0 +1,144 @@
+//! Calls into the exported NSS functions the way glibc does, against the runtime directory of the test binary.
+
+// Each test binary uses only some of the helpers
+#![allow(dead_code)]
+
+use std::ffi::{CStr, CString};
+use std::path::PathBuf;
+use std::sync::Once;
+
+pub const SUCCESS: i32 = 1;
+pub const NOTFOUND: i32 = 0;
+pub const UNAVAIL: i32 = -1;
+pub const TRYAGAIN: i32 = -2;
+
+/// Runtime directory of this test binary, handed to the NSS module through its test hooks along with `nss.json` in it
+/// as the fallback file
+pub fn runtime_dir(name: &str) -> PathBuf {
+    static INIT: Once = Once::new();
+    let dir = std::env::temp_dir().join(format!("sectora-{}-{}", name, std::process::id()));
+    INIT.call_once(|| {
+            std::fs::create_dir_all(&dir).expect("create runtime dir");
+            nss_sectora::set_test_runtime_dir(&dir);
+            nss_sectora::set_test_fallback_path(&dir.join("nss.json"));
+        });
+    dir
+}
+
+fn string(ptr: *const libc::c_char) -> String { unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned() }
+
+/// Status, errno and the name and id of the entry on success
+pub type Lookup = (i32, i32, Option<(String, u32)>);
+
+fn passwd(status: i32, errno: i32, pw: &libc::passwd) -> Lookup {
+    (status, errno, (status == SUCCESS).then(|| (string(pw.pw_name), pw.pw_uid)))
+}
+
+fn group(status: i32, errno: i32, gr: &libc::group) -> Lookup {
+    (status, errno, (status == SUCCESS).then(|| (string(gr.gr_name), gr.gr_gid)))
+}
+
+pub fn getpwnam(name: &str, buflen: usize) -> Lookup {
+    let name = CString::new(name).unwrap();
+    let mut pw: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; buflen];
+    let mut errno = 0;
+    let status = unsafe {
+        nss_sectora::_nss_sectora_getpwnam_r(name.as_ptr(),
+                                             (&mut pw as *mut libc::passwd).cast(),
+                                             buf.as_mut_ptr(),
+                                             buflen,
+                                             &mut errno)
+    };
+    passwd(status, errno, &pw)
+}
+
+pub fn getpwent(buflen: usize) -> Lookup {
+    let mut pw: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; buflen];
+    let mut errno = 0;
+    let status = unsafe {
+        nss_sectora::_nss_sectora_getpwent_r((&mut pw as *mut libc::passwd).cast(),
+                                             buf.as_mut_ptr(),
+                                             buflen,
+                                             &mut errno)
+    };
+    passwd(status, errno, &pw)
+}
+
+pub fn setpwent() -> i32 { unsafe { nss_sectora::_nss_sectora_setpwent() } }
+
+pub fn endpwent() -> i32 { unsafe { nss_sectora::_nss_sectora_endpwent() } }
+
+pub fn getgrnam(name: &str, buflen: usize) -> Lookup {
+    let name = CString::new(name).unwrap();
+    let mut gr: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; buflen];
+    let mut errno = 0;
+    let status = unsafe {
+        nss_sectora::_nss_sectora_getgrnam_r(name.as_ptr(),
+                                             (&mut gr as *mut libc::group).cast(),
+                                             buf.as_mut_ptr(),
+                                             buflen,
+                                             &mut errno)
+    };
+    group(status, errno, &gr)
+}
+
+pub fn getgrgid(gid: u32, buflen: usize) -> Lookup {
+    let mut gr: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; buflen];
+    let mut errno = 0;
+    let status = unsafe {
+        nss_sectora::_nss_sectora_getgrgid_r(gid,
+                                             (&mut gr as *mut libc::group).cast(),
+                                             buf.as_mut_ptr(),
+                                             buflen,
+                                             &mut errno)
+    };
+    group(status, errno, &gr)
+}
+
+/// Members of the group, with the buffer starting `misalign` bytes past an 8-byte boundary
+pub fn getgrnam_members(name: &str, buflen: usize, misalign: usize) -> (i32, i32, Vec<String>) {
+    let name = CString::new(name).unwrap();
+    let mut gr: libc::group = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0u64; (buflen + misalign).div_ceil(8)];
+    let mut errno = 0;
+    let status = unsafe {
+        nss_sectora::_nss_sectora_getgrnam_r(name.as_ptr(),
+                                             (&mut gr as *mut libc::group).cast(),
+                                             buf.as_mut_ptr().cast::<libc::c_char>().add(misalign),
+                                             buflen,
+                                             &mut errno)
+    };
+    let mut members = Vec::new();
+    if status == SUCCESS {
+        for i in 0.. {
+            let member = unsafe { *gr.gr_mem.add(i) };
+            if member.is_null() {
+                break;
+            }
+            members.push(string(member));
+        }
+    }
+    (status, errno, members)
+}
+
+pub fn setgrent() -> i32 { unsafe { nss_sectora::_nss_sectora_setgrent() } }
+
+/// Status and errno only, as no test needs the entry
+pub fn getspnam(name: &str, buflen: usize) -> (i32, i32) {
+    let name = CString::new(name).unwrap();
+    let mut sp: libc::spwd = unsafe { std::mem::zeroed() };
+    let mut buf = vec![0 as libc::c_char; buflen];
+    let mut errno = 0;
+    let status = unsafe {
+        nss_sectora::_nss_sectora_getspnam_r(name.as_ptr(),
+                                             (&mut sp as *mut libc::spwd).cast(),
+                                             buf.as_mut_ptr(),
+                                             buflen,
+                                             &mut errno)
+    };
+    (status, errno)
+}
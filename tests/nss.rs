@@ -0,0 +1,151 @@
+//! NSS statuses against a fake daemon speaking the socket protocol.
+
+mod common;
+
+use common::*;
+use serde_json::{Value, json};
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::Once;
+use std::time::Duration;
+
+/// Longer than the read timeout of the module, so that the daemon looks busy
+const STALL: Duration = Duration::from_secs(6);
+
+fn pw(login: &str, uid: u64) -> Value {
+    json!({ "Pw": { "login": login, "uid": uid, "gid": 2019, "home": format!("/home/{}", login), "sh": "/bin/sh" } })
+}
+
+fn gr() -> Value {
+    json!({ "Gr": { "sector": { "sector": { "id": 9, "name": "sector9", "sector_type": "Team" },
+                                "gid": 2019,
+                                "group": null,
+                                "members": { "alice": { "id": 2001, "login": "alice" } } } } })
+}
+
+fn not_found() -> Value { json!({ "Error": { "message": "not found" } }) }
+
+fn read_message(stream: &mut UnixStream) -> Option<(Value, Value)> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).ok()?;
+    let mut body = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut body).ok()?;
+    let envelope: Value = serde_json::from_slice(&body).ok()?;
+    Some((envelope["version"].clone(), envelope["message"].clone()))
+}
+
+fn write_message(stream: &mut UnixStream, version: &Value, message: Value) {
+    let body = serde_json::to_vec(&json!({ "version": version, "message": message })).unwrap();
+    stream.write_all(&(body.len() as u32).to_be_bytes()).unwrap();
+    stream.write_all(&body).unwrap();
+}
+
+/// Answers like `sectorad` with alice and bob in sector9 and a user whose name holds a NUL byte; `stall` never gets an
+/// answer, and group enumerations are refused as over the limit
+fn serve(mut stream: UnixStream) {
+    let mut entries = Vec::new();
+    while let Some((version, message)) = read_message(&mut stream) {
+        let reply = match &message {
+            Value::Object(m) if m.contains_key("Hello") => message.clone(),
+            _ if message == json!({ "Pw": { "Nam": "stall" } }) => {
+                std::thread::sleep(STALL);
+                return;
+            }
+            _ if message == json!({ "Pw": { "Nam": "alice" } }) => pw("alice", 2001),
+            _ if message == json!({ "Pw": { "Nam": "nul" } }) => pw("nul\u{0}", 2003),
+            _ if message == json!({ "Pw": { "Ent": "Set" } }) => {
+                entries = vec![pw("alice", 2001), pw("nul\u{0}", 2003), pw("bob", 2002)];
+                json!("Success")
+            }
+            _ if message == json!({ "Pw": { "Ent": "Get" } }) => {
+                if entries.is_empty() {
+                    not_found()
+                } else {
+                    entries.remove(0)
+                }
+            }
+            _ if message == json!({ "Pw": { "Ent": "End" } }) => json!("Success"),
+            _ if message == json!({ "Gr": { "Nam": "sector9" } }) => gr(),
+            _ if message == json!({ "Gr": { "Ent": "Set" } }) => {
+                json!({ "Error": { "message": "too many enumerations" } })
+            }
+            _ => not_found(),
+        };
+        write_message(&mut stream, &version, reply);
+    }
+}
+
+fn daemon() {
+    static START: Once = Once::new();
+    let dir = runtime_dir("nss");
+    START.call_once(|| {
+             let listener = UnixListener::bind(dir.join("sectorad.sock")).expect("bind fake daemon");
+             std::thread::spawn(move || {
+                 for stream in listener.incoming().flatten() {
+                     std::thread::spawn(move || serve(stream));
+                 }
+             });
+         });
+}
+
+#[test]
+fn found() {
+    daemon();
+    assert_eq!(getpwnam("alice", 1024),
+               (SUCCESS, 0, Some((String::from("alice"), 2001))));
+    assert_eq!(getgrnam("sector9", 1024),
+               (SUCCESS, 0, Some((String::from("sector9"), 2019))));
+}
+
+#[test]
+fn not_found_is_enoent() {
+    daemon();
+    assert_eq!(getpwnam("nobody", 1024), (NOTFOUND, libc::ENOENT, None));
+    assert_eq!(getgrgid(1, 1024), (NOTFOUND, libc::ENOENT, None));
+    assert_eq!(getspnam("nobody", 1024), (NOTFOUND, libc::ENOENT));
+}
+
+#[test]
+fn small_buffer_is_erange() {
+    daemon();
+    assert_eq!(getpwnam("alice", 8), (TRYAGAIN, libc::ERANGE, None));
+    assert_eq!(getgrnam("sector9", 8), (TRYAGAIN, libc::ERANGE, None));
+}
+
+#[test]
+fn member_pointers_are_aligned_and_fit_the_buffer() {
+    daemon();
+    // "sector9", "x" and "alice" with their NULs take 16 bytes, followed by the padding and two pointers
+    for misalign in 0..8 {
+        let pad = (8 - misalign % 8) % 8;
+        assert_eq!(getgrnam_members("sector9", 32 + pad, misalign),
+                   (SUCCESS, 0, vec![String::from("alice")]));
+        assert_eq!(getgrnam_members("sector9", 31 + pad, misalign),
+                   (TRYAGAIN, libc::ERANGE, Vec::new()));
+    }
+}
+
+#[test]
+fn nul_in_a_name_is_not_found() {
+    daemon();
+    // Not ERANGE, which glibc would retry with ever larger buffers
+    assert_eq!(getpwnam("nul", 1024), (NOTFOUND, libc::ENOENT, None));
+}
+
+#[test]
+fn busy_daemon_is_eagain() {
+    daemon();
+    assert_eq!(getpwnam("stall", 1024), (TRYAGAIN, libc::EAGAIN, None));
+    assert_eq!(setgrent(), TRYAGAIN);
+}
+
+#[test]
+fn enumeration_retries_the_entry_on_erange_and_skips_a_nul() {
+    daemon();
+    assert_eq!(setpwent(), SUCCESS);
+    assert_eq!(getpwent(8), (TRYAGAIN, libc::ERANGE, None));
+    assert_eq!(getpwent(1024), (SUCCESS, 0, Some((String::from("alice"), 2001))));
+    assert_eq!(getpwent(1024), (SUCCESS, 0, Some((String::from("bob"), 2002))));
+    assert_eq!(getpwent(1024), (NOTFOUND, libc::ENOENT, None));
+    assert_eq!(endpwent(), SUCCESS);
+}
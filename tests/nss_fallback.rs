@@ -0,0 +1,70 @@
+//! Lookups answered from the fallback file while no daemon listens on the socket, and only then.
+
+mod common;
+
+use common::*;
+use serde_json::json;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+
+/// Replaces the file by a rename, as the daemon does
+fn write_fallback(path: &Path, logins: &[(&str, u64)], mode: u32) {
+    let passwd: Vec<_> = logins.iter()
+                               .map(|(login, uid)| {
+                                   json!({"login": login, "uid": uid, "gid": 2019,
+                                          "home": format!("/home/{}", login), "sh": "/bin/bash"})
+                               })
+                               .collect();
+    let members: serde_json::Map<_, _> =
+        logins.iter()
+              .map(|(login, uid)| (login.to_string(), json!({"id": uid, "login": login})))
+              .collect();
+    let fallback = json!({"version": 1, "written_at": 0, "passwd": passwd,
+                          "group": [{"sector": {"id": 19, "name": "sector9", "sector_type": "Team"},
+                                     "gid": 2019, "group": "sector9", "members": members}]});
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec(&fallback).unwrap()).expect("write fallback");
+    fs::set_permissions(&tmp, fs::Permissions::from_mode(mode)).expect("chmod fallback");
+    fs::rename(&tmp, path).expect("rename fallback");
+}
+
+/// One test, as the fallback file is shared by the whole binary
+#[test]
+fn fallback_answers_while_daemon_is_down() {
+    let path = runtime_dir("fallback").join("nss.json");
+    write_fallback(&path, &[("hunter", 2001), ("alice", 2003)], 0o644);
+    assert_eq!(getpwnam("alice", 1024),
+               (SUCCESS, 0, Some((String::from("alice"), 2003))));
+    assert_eq!(getpwnam("bob", 1024), (NOTFOUND, libc::ENOENT, None));
+    assert_eq!(getgrgid(2019, 1024),
+               (SUCCESS, 0, Some((String::from("sector9"), 2019))));
+    assert_eq!(getgrnam("sector9", 1024),
+               (SUCCESS, 0, Some((String::from("sector9"), 2019))));
+    assert_eq!(getspnam("alice", 1024), (UNAVAIL, libc::ENOENT));
+    assert_eq!(setpwent(), SUCCESS);
+    assert_eq!(getpwent(1024), (SUCCESS, 0, Some((String::from("hunter"), 2001))));
+    assert_eq!(getpwent(1024), (SUCCESS, 0, Some((String::from("alice"), 2003))));
+    assert_eq!(endpwent(), SUCCESS);
+
+    // A new file is read again even within the same second, as the daemon replaces it by a rename
+    write_fallback(&path, &[("bob", 2004)], 0o644);
+    assert_eq!(getpwnam("bob", 1024), (SUCCESS, 0, Some((String::from("bob"), 2004))));
+    assert_eq!(getpwnam("alice", 1024), (NOTFOUND, libc::ENOENT, None));
+
+    // A file others could have written is ignored, even when it was read before
+    for mode in [0o664, 0o646] {
+        write_fallback(&path, &[("bob", 2004)], mode);
+        assert_eq!(getpwnam("bob", 1024), (UNAVAIL, libc::ENOENT, None));
+        assert_eq!(getgrgid(2019, 1024), (UNAVAIL, libc::ENOENT, None));
+    }
+    write_fallback(&path, &[("bob", 2004)], 0o644);
+    assert_eq!(getpwnam("bob", 1024), (SUCCESS, 0, Some((String::from("bob"), 2004))));
+
+    // A socket that accepts but never answers, as while systemd holds it for a daemon that is starting, is a busy
+    // daemon and not a stopped one
+    let _listener = UnixListener::bind(path.with_file_name("sectorad.sock")).expect("bind");
+    assert_eq!(getpwnam("bob", 1024), (TRYAGAIN, libc::EAGAIN, None));
+    assert_eq!(setpwent(), TRYAGAIN);
+}
@@ -0,0 +1,22 @@
+//! NSS statuses when no daemon listens on the socket and no fallback file is left.
+
+mod common;
+
+use common::*;
+use std::os::unix::net::UnixListener;
+
+/// One test, as the socket file is shared by the whole binary
+#[test]
+fn daemon_not_running_is_unavail() {
+    let dir = runtime_dir("unavailable");
+    assert_eq!(getpwnam("alice", 1024), (UNAVAIL, libc::ENOENT, None));
+    assert_eq!(getgrgid(2019, 1024), (UNAVAIL, libc::ENOENT, None));
+    assert_eq!(getspnam("alice", 1024), (UNAVAIL, libc::ENOENT));
+    assert_eq!(setpwent(), UNAVAIL);
+    assert_eq!(getpwent(1024), (UNAVAIL, libc::ENOENT, None));
+    assert_eq!(endpwent(), SUCCESS);
+
+    // A socket file left behind by a daemon that has exited refuses connections
+    drop(UnixListener::bind(dir.join("sectorad.sock")).expect("bind"));
+    assert_eq!(getpwnam("alice", 1024), (UNAVAIL, libc::ENOENT, None));
+}